@@ -0,0 +1,65 @@
+//! Verified bit-range accessors for the descriptor and register layouts.
+//! A field is described by the index of its lowest bit (`start`) and its `width` in bits,
+//! so the datasheet range `[47:32]` becomes `start = 32, width = 16`.
+
+use prusti_contracts::*;
+
+#[pure]
+#[requires(0 < width && width <= 64)]
+pub fn mask_u64(width: u32) -> u64 {
+    if width == 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    }
+}
+
+#[pure]
+#[requires(0 < width && width <= 32)]
+pub fn mask_u32(width: u32) -> u32 {
+    if width == 32 {
+        u32::MAX
+    } else {
+        (1u32 << width) - 1
+    }
+}
+
+/// Returns bits `[start + width - 1 : start]` of `value`, shifted down to bit 0.
+#[pure]
+#[requires(0 < width && width <= 64 && start <= 64 - width)]
+#[ensures(result <= mask_u64(width))]
+pub fn get_bits_u64(value: u64, start: u32, width: u32) -> u64 {
+    (value >> start) & mask_u64(width)
+}
+
+/// Returns bits `[start + width - 1 : start]` of `value`, shifted down to bit 0.
+#[pure]
+#[requires(0 < width && width <= 32 && start <= 32 - width)]
+#[ensures(result <= mask_u32(width))]
+pub fn get_bits_u32(value: u32, start: u32, width: u32) -> u32 {
+    (value >> start) & mask_u32(width)
+}
+
+/// Returns `value` with bits `[start + width - 1 : start]` replaced by `field`.
+/// All bits outside of the range are left untouched.
+#[pure]
+#[requires(0 < width && width <= 64 && start <= 64 - width)]
+#[requires(field <= mask_u64(width))]
+#[ensures(get_bits_u64(result, start, width) == field)]
+#[ensures(result & !(mask_u64(width) << start) == value & !(mask_u64(width) << start))]
+pub fn set_bits_u64(value: u64, start: u32, width: u32, field: u64) -> u64 {
+    let mask = mask_u64(width) << start;
+    (value & !mask) | (field << start)
+}
+
+/// Returns `value` with bits `[start + width - 1 : start]` replaced by `field`.
+/// All bits outside of the range are left untouched.
+#[pure]
+#[requires(0 < width && width <= 32 && start <= 32 - width)]
+#[requires(field <= mask_u32(width))]
+#[ensures(get_bits_u32(result, start, width) == field)]
+#[ensures(result & !(mask_u32(width) << start) == value & !(mask_u32(width) << start))]
+pub fn set_bits_u32(value: u32, start: u32, width: u32, field: u32) -> u32 {
+    let mask = mask_u32(width) << start;
+    (value & !mask) | (field << start)
+}
@@ -0,0 +1,195 @@
+//! Allocation of memory that the NIC can access through DMA.
+//!
+//! The driver only needs to know where a region is mapped virtually and physically,
+//! so descriptor rings and the `Mempool` are generic over the [`DmaMemory`] that backs them.
+//! In Theseus this is `MappedPages`; a userspace driver can use hugepages translated through `/proc/self/pagemap`,
+//! and tests can use the heap-backed [`HeapDmaAllocator`], which pretends that physical addresses equal virtual addresses.
+
+use prusti_contracts::*;
+#[cfg(feature = "std")]
+use core::{alloc::Layout, ptr::NonNull};
+use core::marker::PhantomData;
+use crate::structs::{PhysicalAddress, AdvancedRxDescriptor, AdvancedTxDescriptor};
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::result_spec::*;
+
+/// A physically contiguous region of memory, which stays mapped for as long as the object lives.
+pub trait DmaMemory {
+    /// The virtual address of the first byte of the region.
+    #[pure]
+    fn start_address(&self) -> usize;
+
+    /// The physical address of the first byte of the region.
+    #[pure]
+    fn start_paddr(&self) -> PhysicalAddress;
+
+    #[pure]
+    #[ensures(result <= usize::MAX - self.start_paddr().value())]
+    #[ensures(result <= usize::MAX - self.start_address())]
+    fn size_in_bytes(&self) -> usize;
+
+    /// Translates an `offset` into the region to the physical address it is mapped to.
+    #[pure]
+    #[requires(offset < self.size_in_bytes())]
+    #[ensures(result.value() >= self.start_paddr().value())]
+    #[ensures(result.value() < self.start_paddr().value() + self.size_in_bytes())]
+    fn translate(&self, offset: usize) -> PhysicalAddress {
+        PhysicalAddress::new(self.start_paddr().value() + offset)
+    }
+}
+
+/// Hands out regions of DMA-able memory.
+pub trait DmaAllocator {
+    type Memory: DmaMemory;
+
+    /// Allocates a region of at least `size` bytes, whose physical address is a multiple of `align`.
+    /// Returns the region together with its physical address.
+    #[requires(size > 0 && align > 0)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).0.size_in_bytes() >= size)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).1.value() == peek_result_ref(&result).0.start_paddr().value())]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).1.value() % align == 0)]
+    fn alloc_dma(&mut self, size: usize, align: usize) -> Result<(Self::Memory, PhysicalAddress), &'static str>;
+}
+
+/// A descriptor type which can be placed in a [`DescriptorRing`].
+///
+/// # Safety
+/// An all-zero bit pattern must be a valid value of the type.
+pub unsafe trait Descriptor {}
+
+unsafe impl Descriptor for AdvancedRxDescriptor {}
+unsafe impl Descriptor for AdvancedTxDescriptor {}
+
+/// The required alignment of a descriptor ring, in bytes.
+pub const DESC_RING_ALIGN: usize = 128;
+
+/// A ring of `D` descriptors stored in DMA memory `M`, so that the NIC can be pointed to it.
+pub struct DescriptorRing<D: Descriptor, M: DmaMemory> {
+    mp: M,
+    num_descs: usize,
+    _desc: PhantomData<D>,
+}
+
+impl<D: Descriptor, M: DmaMemory> DescriptorRing<D, M> {
+    /// Allocates a zeroed ring of `num_descs` descriptors from `allocator`.
+    #[requires(num_descs > 0 && num_descs <= u16::MAX as usize)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).len() == num_descs)]
+    pub fn new<A: DmaAllocator<Memory = M>>(allocator: &mut A, num_descs: usize) -> Result<DescriptorRing<D, M>, &'static str> {
+        let size = ring_size::<D>(num_descs)?;
+        let (mp, _paddr) = allocator.alloc_dma(size, DESC_RING_ALIGN)?;
+        zero_memory(&mp, size);
+        Ok(DescriptorRing { mp, num_descs, _desc: PhantomData })
+    }
+
+    #[pure]
+    pub fn len(&self) -> usize {
+        self.num_descs
+    }
+
+    /// The physical address of the first descriptor, which is written to the base address registers of the queue.
+    #[pure]
+    pub fn paddr(&self) -> PhysicalAddress {
+        self.mp.start_paddr()
+    }
+
+    #[trusted]
+    #[ensures(result.len() == self.len())]
+    pub fn descs(&self) -> &[D] {
+        // SAFETY: `new()` checked that `mp` is large enough for `num_descs` descriptors and zeroed them, which is a valid `D`
+        unsafe { core::slice::from_raw_parts(self.mp.start_address() as *const D, self.num_descs) }
+    }
+
+    #[trusted]
+    #[ensures(result.len() == old(self.len()))]
+    #[after_expiry(self.len() == old(self.len()))]
+    pub fn descs_mut(&mut self) -> &mut [D] {
+        // SAFETY: see `descs()`, and the descriptors can only be reached through the exclusive borrow of the ring
+        unsafe { core::slice::from_raw_parts_mut(self.mp.start_address() as *mut D, self.num_descs) }
+    }
+}
+
+#[trusted]
+fn ring_size<D>(num_descs: usize) -> Result<usize, &'static str> {
+    core::mem::size_of::<D>().checked_mul(num_descs).ok_or("descriptor ring is too large")
+}
+
+#[trusted]
+#[requires(size <= mp.size_in_bytes())]
+fn zero_memory<M: DmaMemory>(mp: &M, size: usize) {
+    // SAFETY: the region is mapped and at least `size` bytes long
+    unsafe { core::ptr::write_bytes(mp.start_address() as *mut u8, 0, size) }
+}
+
+/// A [`DmaAllocator`] for tests, which allocates from the heap and uses virtual addresses as physical addresses.
+#[cfg(feature = "std")]
+pub struct HeapDmaAllocator;
+
+/// A region of heap memory allocated by [`HeapDmaAllocator`], which is freed on drop.
+#[cfg(feature = "std")]
+pub struct HeapDma {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+#[cfg(feature = "std")]
+impl DmaMemory for HeapDma {
+    #[pure]
+    #[trusted]
+    fn start_address(&self) -> usize {
+        self.ptr.as_ptr() as usize
+    }
+
+    #[pure]
+    #[trusted]
+    fn start_paddr(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.start_address())
+    }
+
+    #[pure]
+    #[trusted]
+    fn size_in_bytes(&self) -> usize {
+        self.layout.size()
+    }
+}
+
+#[cfg(feature = "std")]
+impl DmaAllocator for HeapDmaAllocator {
+    type Memory = HeapDma;
+
+    #[trusted]
+    fn alloc_dma(&mut self, size: usize, align: usize) -> Result<(HeapDma, PhysicalAddress), &'static str> {
+        if size == 0 {
+            return Err("cannot allocate an empty region");
+        }
+        let layout = Layout::from_size_align(size, align).map_err(|_| "invalid size or alignment")?;
+        // SAFETY: the size is non-zero
+        let ptr = NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) }).ok_or("out of memory")?;
+        let mem = HeapDma { ptr, layout };
+        let paddr = mem.start_paddr();
+        Ok((mem, paddr))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for HeapDma {
+    #[trusted]
+    fn drop(&mut self) {
+        // SAFETY: `ptr` was allocated with `layout` by `HeapDmaAllocator::alloc_dma()`
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_ring_is_aligned_and_zeroed() {
+        let ring: DescriptorRing<AdvancedTxDescriptor, HeapDma> = DescriptorRing::new(&mut HeapDmaAllocator, 8).unwrap();
+        assert_eq!(ring.len(), 8);
+        assert_eq!(ring.descs().len(), 8);
+        assert_eq!(ring.paddr().value() % DESC_RING_ALIGN, 0);
+        assert!(ring.descs().iter().all(|desc| !desc.desc_done()));
+    }
+}
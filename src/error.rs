@@ -0,0 +1,25 @@
+//! The error type shared by the receive, transmit and filter functions of the driver.
+
+use prusti_contracts::*;
+use crate::filter::FilterError;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DriverError {
+    /// The transmit ring has no free descriptor, so no packet could be sent.
+    QueueFull,
+    /// The mempool has no free buffer left.
+    PoolExhausted,
+    /// The NIC reported a descriptor index which lies outside of the ring.
+    InvalidRingState,
+    /// A received packet spans more than one descriptor, which the driver does not handle.
+    MultiDescriptorUnsupported,
+    /// Adding a filter failed.
+    Filter(FilterError),
+}
+
+impl From<FilterError> for DriverError {
+    #[ensures(result == DriverError::Filter(e))]
+    fn from(e: FilterError) -> Self {
+        DriverError::Filter(e)
+    }
+}
@@ -1,6 +1,9 @@
 use prusti_contracts::*;
-use crate::option_spec::*;
-use crate::result_spec::*;
+use crate::error::DriverError;
+use verified_specs::snapshot::Snapshot;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{option_spec::*, result_spec::*};
 
 pub struct EnableFilter(usize);
 
@@ -67,6 +70,15 @@ impl FilterParameters {
     }
 }
 
+impl Snapshot for FilterParameters {
+    type Model = FilterParameters;
+
+    #[pure]
+    fn snapshot(&self) -> FilterParameters {
+        *self
+    }
+}
+
 // #[ensures(result.is_ok() ==> {
 //     let idx = peek_result_ref(&result).value(); 
 //     filters[idx].is_some() 
@@ -87,7 +99,7 @@ impl FilterParameters {
 //         if filters[i].is_some() {
 //             let filter = filters[i].unwrap();
 //             if filter.parameters_equal(&new_filter) {
-//                 return Err(FilterError::IdenticalFilter(i));
+//                 return Err(FilterError::IdenticalFilter(i).into());
 //             }
 //         } else {
 //             filters[i] = Some(new_filter);
@@ -109,11 +121,12 @@ impl FilterParameters {
 })]
 #[ensures(result.is_err() ==> {
     match peek_err(&result) {
-        FilterError::NoneAvailable => forall(|i: usize|( 0 <= i && i < 128 ==> filters[i].is_some())),
-        FilterError::IdenticalFilter(idx) => filters[idx].is_some() && peek_option(&filters[idx]).parameters_equal(&new_filter),
+        DriverError::Filter(FilterError::NoneAvailable) => forall(|i: usize|( 0 <= i && i < 128 ==> filters[i].is_some())),
+        DriverError::Filter(FilterError::IdenticalFilter(idx)) => filters[idx].is_some() && peek_option(&filters[idx]).parameters_equal(&new_filter),
+        _ => false,
     } && forall(|i: usize|( 0 <= i && i < 128 ==> filters[i] == old(filters[i])))
 })]
-pub fn check_and_add_filter(filters: &mut [Option<FilterParameters>; 128], new_filter: FilterParameters) -> Result<EnableFilter, FilterError> {
+pub fn check_and_add_filter(filters: &mut [Option<FilterParameters>; 128], new_filter: FilterParameters) -> Result<EnableFilter, DriverError> {
     let mut i = 0;
     let mut unused_filter = None ;
 
@@ -125,23 +138,19 @@ pub fn check_and_add_filter(filters: &mut [Option<FilterParameters>; 128], new_f
 
         if filters[i].is_some() {
             if filters[i].unwrap().parameters_equal(&new_filter) {
-                return Err(FilterError::IdenticalFilter(i));
+                return Err(FilterError::IdenticalFilter(i).into());
             }
         } else if unused_filter.is_none(){
             unused_filter = Some(i);
         }
         i += 1;
     }
-    if unused_filter.is_some() {
-        let filter_idx = unused_filter.unwrap();
-        filters[filter_idx] = Some(new_filter);
-        Ok(EnableFilter(filter_idx))
-    } else {
-        Err(FilterError::NoneAvailable)
-    }
+    let filter_idx = unused_filter.ok_or(FilterError::NoneAvailable)?;
+    filters[filter_idx] = Some(new_filter);
+    Ok(EnableFilter(filter_idx))
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum FilterError {
     NoneAvailable,
     IdenticalFilter(usize)
@@ -0,0 +1,111 @@
+//! Macros for declaring the memory layout of registers and descriptors.
+//!
+//! `register_layout!` declares a `#[repr(C)]` struct whose fields are annotated with their offset from the datasheet.
+//! The offsets and total size are checked at compile time, so a missing or wrongly sized padding array is a build error.
+//!
+//! `bitfields!` declares read accessors for a bit range of a `Volatile<u64>` or `Volatile<u32>` field.
+//! The accessors are built on the verified functions in [`crate::bits`] and come with a Prusti spec bounding the result by the field width.
+//! The bit ranges are also checked at compile time to lie within the field.
+
+/// Declares a `#[repr(C)]` struct where every field is preceded by its absolute offset.
+/// The `base` is the offset of the first byte of the struct, and `size` is the total size in bytes.
+///
+/// ```ignore
+/// register_layout! {
+///     pub struct RegistersRx, base 0x1000, size 64 {
+///         /// Receive Descriptor Base Address Low
+///         0x1000 => pub rdbal: Volatile<u32>,
+///         ...
+///     }
+/// }
+/// ```
+macro_rules! register_layout {
+    (
+        $(#[$smeta:meta])*
+        $vis:vis struct $name:ident, base $base:literal, size $size:literal {
+            $(
+                $(#[$fmeta:meta])*
+                $off:literal => $fvis:vis $field:ident : $fty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$smeta])*
+        #[repr(C)]
+        $vis struct $name {
+            $(
+                $(#[$fmeta])*
+                $fvis $field: $fty,
+            )*
+        }
+
+        // Prusti's toolchain predates `offset_of!`, and the layout doesn't affect verification.
+        #[cfg(not(prusti))]
+        const _: () = {
+            assert!(core::mem::size_of::<$name>() == $size, concat!("size of ", stringify!($name), " does not match its layout"));
+            $(
+                assert!(
+                    core::mem::offset_of!($name, $field) == $off - $base,
+                    concat!("offset of ", stringify!($name), "::", stringify!($field), " does not match its layout")
+                );
+            )*
+        };
+    };
+}
+
+/// Declares read accessors for bit ranges of `Volatile` fields.
+/// Each accessor names the field, its integer type (`u64` or `u32`) and the range as `bits(start, width)`.
+///
+/// ```ignore
+/// bitfields! {
+///     impl AdvancedRxDescriptor {
+///         /// Returns the number of bytes posted to the packet buffer
+///         pub get_pkt_len: header_buffer_address, u64, bits(32, 16);
+///     }
+/// }
+/// ```
+macro_rules! bitfields {
+    (
+        impl $name:ident {
+            $(
+                $(#[$meta:meta])*
+                $vis:vis $getter:ident : $field:ident, $ty:ident, bits($start:expr, $width:expr);
+            )*
+        }
+    ) => {
+        impl $name {
+            $(
+                $crate::layout::bitfields!(@getter $(#[$meta])* $vis $getter, $field, $ty, $start, $width);
+            )*
+        }
+
+        const _: () = {
+            $(
+                assert!(
+                    $width > 0 && $start + $width <= <$ty>::BITS,
+                    concat!("bit range of ", stringify!($name), "::", stringify!($getter), " does not fit in ", stringify!($ty))
+                );
+            )*
+        };
+    };
+
+    (@getter $(#[$meta:meta])* $vis:vis $getter:ident, $field:ident, u64, $start:expr, $width:expr) => {
+        $(#[$meta])*
+        #[inline(always)]
+        #[prusti_contracts::ensures(result <= $crate::bits::mask_u64($width))]
+        $vis fn $getter(&self) -> u64 {
+            $crate::bits::get_bits_u64(self.$field.read(), $start, $width)
+        }
+    };
+
+    (@getter $(#[$meta:meta])* $vis:vis $getter:ident, $field:ident, u32, $start:expr, $width:expr) => {
+        $(#[$meta])*
+        #[inline(always)]
+        #[prusti_contracts::ensures(result <= $crate::bits::mask_u32($width))]
+        $vis fn $getter(&self) -> u32 {
+            $crate::bits::get_bits_u32(self.$field.read(), $start, $width)
+        }
+    };
+}
+
+pub(crate) use register_layout;
+pub(crate) use bitfields;
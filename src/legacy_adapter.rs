@@ -0,0 +1,389 @@
+//! Runs code written against the owned-buffer API of [`crate::tx_rx_old`] on top of the `Mempool`-based
+//! [`tx_rx::rx_batch`] and [`tx_rx::tx_batch`].
+//!
+//! In the legacy design a `PacketBufferS` owns its memory and carries its physical address and length.
+//! An [`OwnedPacketBuffer`] carries the same `length` field next to the `Mempool` handle which backs it,
+//! so code which reads and writes the length works unchanged. The physical address is fixed by the slot of the buffer,
+//! so it is only readable through [`OwnedPacketBuffer::phys_addr`]. Code which uses the `mp` field has to be ported.
+//!
+//! Both designs are compared on a [`BufferModel`]: the physical address of the packet data and its length,
+//! which is all that the NIC and the layers above observe of a buffer. A legacy buffer is abstracted by [`legacy_model`]
+//! and a `Mempool` buffer by [`mempool_model`], or by [`OwnedPacketBuffer::model`] while the caller holds it.
+//! The legacy `rx_batch` and `tx_batch` state which buffer ends up where by comparing the models of buffers,
+//! and the functions here restate those postconditions with `legacy_model` replaced by the models of this design,
+//! next to the ones about the ring indices and the lengths of the vectors, so they refine the legacy contracts.
+//! A `PacketBufferS` keeps its address for good, but the address of a `Mempool` buffer moves with its headroom,
+//! so the models of a buffer in a ring are taken from the pool after the call.
+//! The legacy contracts say nothing about the lengths of the buffers, so the refinement doesn't either.
+//!
+//! The postconditions differ where the designs do:
+//! * `used_buffers` grows by at most, instead of exactly, the number of cleaned descriptors,
+//!   because a buffer which is still referenced through a handle from [`Mempool::clone_ref`] isn't handed back.
+//! * `tx_batch` only writes the tail register when it sent a packet, and fails when the NIC reports
+//!   a transmit head outside of the ring.
+//! * When `rx_batch` fails, the packets received before the failure are still appended to `buffers`,
+//!   and `rx_batch` also fails when the pool runs out of buffers.
+//! * The legacy `tx_batch` pairs the `i`-th sent buffer with `buffers[buffers.len() - i]`, counting from the length
+//!   after the call, which is only the buffer that was sent if a single one was. `tx_batch` here pairs it with
+//!   `old(buffers)[old(buffers.len()) - 1 - i]`, the one `pop` sends `i`-th.
+//!
+//! The receive side additionally states that the model of every received buffer is the one the `Mempool` has for it.
+
+use prusti_contracts::*;
+use verified_specs::vector_spec::VecWrapper;
+use verified_specs::snapshot::Snapshot;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{result_spec::*, vector_spec::VEC_CAPACITY};
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use crate::structs::ETHERNET_FRAME_SIZE;
+use crate::structs::{Mempool, PacketBuffer, PhysicalAddress, AdvancedRxDescriptor, AdvancedTxDescriptor, RxQueueRegisters, TxQueueRegisters};
+use crate::structs_old::PacketBufferS;
+use crate::dma::DmaMemory;
+use crate::ring_buf::RingBuf;
+use crate::error::DriverError;
+use crate::step_counter::StepCounter;
+use crate::tx_rx;
+
+/// The physical address of the packet data of a buffer and the length of the packet.
+pub type BufferModel = (usize, u16);
+
+/// The model of a buffer of the legacy design.
+#[pure]
+pub fn legacy_model(buffer: &PacketBufferS) -> BufferModel {
+    (buffer.phys_addr.value(), buffer.length)
+}
+
+/// The model of `buffer` in the `Mempool` design, where the packet data starts after the headroom of its slot.
+#[pure]
+#[requires(buffer.index() < pool.num_buffers())]
+pub fn mempool_model<P: DmaMemory>(pool: &Mempool<P>, buffer: &PacketBuffer) -> BufferModel {
+    (pool.slot_paddr(buffer.index()) + pool.headroom(buffer), pool.get_length(buffer))
+}
+
+/// A buffer of a `Mempool` with the fields of a legacy `PacketBufferS`.
+pub struct OwnedPacketBuffer {
+    buffer: PacketBuffer,
+    /// The physical address of the packet data, which the `Mempool` determines
+    phys_addr: PhysicalAddress,
+    pub length: u16,
+}
+
+impl OwnedPacketBuffer {
+    /// Wraps `buffer`, taking its address and length from `pool`.
+    #[ensures(result.buffer().index() == buffer.index())]
+    #[ensures(buffer.index() < pool.num_buffers() ==> result.model() == mempool_model(pool, &buffer))]
+    pub fn from_pool<P: DmaMemory>(pool: &Mempool<P>, buffer: PacketBuffer) -> OwnedPacketBuffer {
+        let (phys_addr, length) = pool.buffer_metadata(&buffer);
+        OwnedPacketBuffer { buffer, phys_addr, length }
+    }
+
+    /// Unwraps the `Mempool` buffer, storing the length set through `length` in `pool`.
+    #[ensures(result.index() == old(self.buffer().index()))]
+    #[ensures(old(pool.headroom(self.buffer())) + old(self.length) as usize <= ETHERNET_FRAME_SIZE ==>
+        pool.get_length(&result) == old(self.length))]
+    #[ensures(pool.free_count() == old(pool.free_count()))]
+    pub fn into_pool_buffer<P: DmaMemory>(self, pool: &mut Mempool<P>) -> PacketBuffer {
+        pool.set_length(&self.buffer, self.length);
+        self.buffer
+    }
+
+    /// The handle of the `Mempool` buffer which backs this one.
+    #[pure]
+    pub fn buffer(&self) -> &PacketBuffer {
+        &self.buffer
+    }
+
+    /// The physical address of the packet data, as it was when the buffer was wrapped.
+    #[pure]
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    #[pure]
+    pub fn model(&self) -> BufferModel {
+        (self.phys_addr.value(), self.length)
+    }
+}
+
+impl Snapshot for OwnedPacketBuffer {
+    type Model = (usize, BufferModel);
+
+    #[pure]
+    fn snapshot(&self) -> (usize, BufferModel) {
+        (self.buffer.index(), self.model())
+    }
+}
+
+/// Receives up to `batch_size` packets into `buffers` like the legacy `rx_batch`, replacing them in the ring with buffers from `pool`.
+///
+/// Returns `Err(())` where the legacy function does: when a packet spans several descriptors,
+/// and when the pool runs out of buffers before the batch is done. Unlike the legacy function,
+/// the packets received before that are still appended to `buffers` and the ring is left consistent.
+#[requires(0 <= *rx_cur_stored && (*rx_cur_stored as usize) < rx_descs.len())]
+#[requires(0 <= *rx_cur_stored && (*rx_cur_stored as usize) < rx_bufs_in_use.len())]
+#[requires(rx_descs.len() > 0)]
+#[requires((num_rx_descs as usize) == rx_descs.len())]
+#[requires(rx_bufs_in_use.len() == rx_descs.len())]
+#[requires(buffers.len() + tx_rx::batch_limit(batch_size, num_rx_descs) <= VEC_CAPACITY)]
+#[ensures((result.is_ok() && peek_result(&result) != 0) ==> (old(*rx_cur_stored) as usize + peek_result(&result) as usize) % num_rx_descs as usize == *rx_cur_stored as usize)]
+#[ensures((result.is_ok() && peek_result(&result) == 0) ==> old(*rx_cur_stored) == *rx_cur_stored)]
+#[ensures(result.is_ok() ==> buffers.len() == old(buffers.len()) + peek_result(&result) as usize)]
+#[ensures(result.is_ok() ==> rx_bufs_in_use.len() == old(rx_bufs_in_use.len()))]
+#[ensures(forall(|i: usize| old(buffers.len()) <= i && i < buffers.len() && buffers.index(i).buffer().index() < pool.num_buffers() ==>
+    buffers.index(i).model() == mempool_model(pool, buffers.index(i).buffer())))]
+#[ensures(result.is_ok() ==> forall(|i: usize| i < peek_result(&result) as usize ==> {
+    let rx_cur = (old(*rx_cur_stored) as usize + i) % num_rx_descs as usize;
+    buffers.index(old(buffers.len()) + i).buffer().index() == old(rx_bufs_in_use.index(rx_cur).index())
+}))]
+#[ensures(result.is_ok() ==> forall(|i: usize| i < peek_result(&result) as usize ==> {
+    let rx_cur = (old(*rx_cur_stored) as usize + i) % num_rx_descs as usize;
+    old(rx_bufs_in_use.index(rx_cur).index()) < pool.num_buffers() ==>
+        buffers.index(old(buffers.len()) + i).model().0 == mempool_model(pool, old(rx_bufs_in_use.index(rx_cur))).0
+}))]
+pub fn rx_batch<P: DmaMemory, const N: usize>(
+    rx_descs: &mut [AdvancedRxDescriptor],
+    rx_cur_stored: &mut u16,
+    rx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
+    regs: &mut RxQueueRegisters,
+    num_rx_descs: u16,
+    buffers: &mut VecWrapper<OwnedPacketBuffer>,
+    batch_size: usize,
+    pool: &mut Mempool<P>
+) -> Result<u16, ()> {
+    let mut received = VecWrapper::new();
+    let result = tx_rx::rx_batch(
+        rx_descs, rx_cur_stored, rx_bufs_in_use, regs, num_rx_descs, &mut received, batch_size, pool, &mut StepCounter::new()
+    );
+    wrap_all(&mut received, buffers, pool);
+
+    match result {
+        Ok(outcome) => {
+            if outcome.is_pool_exhausted() {
+                Err(())
+            } else {
+                Ok(outcome.received())
+            }
+        }
+        Err(_) => Err(()),
+    }
+}
+
+/// Cleans the descriptors the NIC is done with and sends up to `batch_size` packets from the back of `buffers`
+/// like the legacy `tx_batch`, storing the `length` of every sent buffer in `pool` first.
+/// Returns the number of packets sent and the number of descriptors that were cleaned.
+///
+/// A full ring isn't an error for the legacy function, so it returns `Ok` with no packets sent.
+/// Buffers which are still referenced through handles from [`Mempool::clone_ref`] aren't handed back
+/// in `used_buffers`, so it grows by at most, instead of exactly, the number of cleaned descriptors.
+/// The legacy function doesn't limit cleaning, so neither does the adapter.
+#[requires(tx_descs.len() > 0)]
+#[requires((num_tx_descs as usize) == tx_descs.len())]
+#[requires(0 <= *tx_cur_stored && *tx_cur_stored < num_tx_descs)]
+#[requires(0 <= *tx_clean_stored && *tx_clean_stored < num_tx_descs)]
+#[requires(used_buffers.len() + num_tx_descs as usize <= VEC_CAPACITY)]
+#[ensures(result.is_ok() ==> (old(*tx_cur_stored) as usize + peek_result(&result).0 as usize) % num_tx_descs as usize == *tx_cur_stored as usize)]
+#[ensures(result.is_ok() ==> buffers.len() == old(buffers.len()) - peek_result(&result).0 as usize)]
+#[ensures(result.is_ok() ==> tx_bufs_in_use.len() == old(tx_bufs_in_use.len()) - peek_result(&result).1 + peek_result(&result).0 as usize)]
+#[ensures(result.is_ok() ==> used_buffers.len() <= old(used_buffers.len()) + peek_result(&result).1)]
+#[ensures((result.is_ok() && peek_result(&result).0 != 0) ==> regs.tdt_model() == *tx_cur_stored as u32)]
+#[ensures(result.is_err() ==> buffers.len() == old(buffers.len()) && *tx_cur_stored == old(*tx_cur_stored))]
+#[ensures(result.is_ok() ==> forall(|i: usize| i < peek_result(&result).0 as usize ==> {
+    let in_use_len = old(tx_bufs_in_use.len()) - peek_result(&result).1;
+    tx_bufs_in_use.index(in_use_len + i).index() == old(buffers.index(old(buffers.len()) - 1 - i).buffer().index())
+}))]
+#[ensures(result.is_ok() ==> forall(|i: usize| i < peek_result(&result).0 as usize ==> {
+    let in_use_len = old(tx_bufs_in_use.len()) - peek_result(&result).1;
+    tx_bufs_in_use.index(in_use_len + i).index() < pool.num_buffers() ==>
+        mempool_model(pool, old(buffers.index(old(buffers.len()) - 1 - i).buffer())).0 == mempool_model(pool, tx_bufs_in_use.index(in_use_len + i)).0
+}))]
+pub fn tx_batch<P: DmaMemory, const N: usize>(
+    tx_descs: &mut [AdvancedTxDescriptor],
+    tx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
+    num_tx_descs: u16,
+    tx_clean_stored: &mut u16,
+    tx_cur_stored: &mut u16,
+    regs: &mut TxQueueRegisters,
+    batch_size: usize,
+    buffers: &mut VecWrapper<OwnedPacketBuffer>,
+    used_buffers: &mut VecWrapper<OwnedPacketBuffer>,
+    pool: &mut Mempool<P>
+) -> Result<(u16, usize), &'static str> {
+    // Only the buffers at the back of `buffers` can be sent in one batch, so only those are unwrapped.
+    let len = buffers.len();
+    let limit = tx_rx::batch_limit(batch_size, num_tx_descs);
+    let num_candidates = if limit < len { limit } else { len };
+    let mut candidates = buffers.drain(len - num_candidates, len);
+    let mut to_send = VecWrapper::new();
+    unwrap_all(&mut candidates, &mut to_send, pool);
+
+    let mut cleaned = VecWrapper::new();
+    let in_use_len = tx_bufs_in_use.len();
+    let result = tx_rx::tx_batch(
+        tx_descs, tx_bufs_in_use, num_tx_descs, tx_clean_stored, tx_cur_stored, regs, batch_size, &mut to_send, &mut cleaned, pool,
+        u16::MAX, &mut StepCounter::new()
+    );
+
+    // The candidates that weren't sent are still in order at the front of `to_send`.
+    wrap_all(&mut to_send, buffers, pool);
+    wrap_all(&mut cleaned, used_buffers, pool);
+
+    match result {
+        Ok(counts) => Ok(counts),
+        Err(DriverError::QueueFull) => Ok((0, in_use_len - tx_bufs_in_use.len())),
+        Err(_) => Err("the NIC reported a transmit head outside of the ring"),
+    }
+}
+
+/// Moves the buffers of `from` to the end of `to` in order, storing their lengths in `pool`.
+#[requires(to.len() + from.len() <= VEC_CAPACITY)]
+#[ensures(from.len() == 0)]
+#[ensures(to.len() == old(to.len()) + old(from.len()))]
+#[ensures(pool.free_count() == old(pool.free_count()))]
+#[ensures(forall(|i: usize| i < old(from.len()) ==> to.index(old(to.len()) + i).index() == old(from.index(i).buffer().index())))]
+fn unwrap_all<P: DmaMemory>(from: &mut VecWrapper<OwnedPacketBuffer>, to: &mut VecWrapper<PacketBuffer>, pool: &mut Mempool<P>) {
+    // Popping the reversed buffers from the back takes them in their original order without shifting the rest.
+    from.reverse();
+    let count = from.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let to_len = to.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let free_count = pool.free_count();
+    let mut i = 0;
+    while i < count {
+        body_invariant!(i < count);
+        body_invariant!(from.len() == count - i);
+        body_invariant!(to.len() == to_len + i);
+        body_invariant!(to_len + count <= VEC_CAPACITY);
+        body_invariant!(pool.free_count() == free_count);
+        body_invariant!(forall(|x: usize| x < from.len() ==> from.index(x).buffer().index() == old(from.index(count - 1 - x).buffer().index())));
+        body_invariant!(forall(|x: usize| x < i ==> to.index(to_len + x).index() == old(from.index(x).buffer().index())));
+
+        if let Some(buffer) = from.pop() {
+            to.push(buffer.into_pool_buffer(pool));
+        }
+        i += 1;
+    }
+}
+
+/// Moves the buffers of `from` to the end of `to` in order, wrapping them with their address and length from `pool`.
+#[requires(to.len() + from.len() <= VEC_CAPACITY)]
+#[ensures(from.len() == 0)]
+#[ensures(to.len() == old(to.len()) + old(from.len()))]
+#[ensures(forall(|i: usize| i < old(to.len()) ==> to.index(i).snapshot() == old(to.index(i).snapshot())))]
+#[ensures(forall(|i: usize| i < old(from.len()) ==> to.index(old(to.len()) + i).buffer().index() == old(from.index(i).index())))]
+#[ensures(forall(|i: usize| old(to.len()) <= i && i < to.len() && to.index(i).buffer().index() < pool.num_buffers() ==>
+    to.index(i).model() == mempool_model(pool, to.index(i).buffer())))]
+fn wrap_all<P: DmaMemory>(from: &mut VecWrapper<PacketBuffer>, to: &mut VecWrapper<OwnedPacketBuffer>, pool: &Mempool<P>) {
+    // Popping the reversed buffers from the back takes them in their original order without shifting the rest.
+    from.reverse();
+    let count = from.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let to_len = to.len();
+    let mut i = 0;
+    while i < count {
+        body_invariant!(i < count);
+        body_invariant!(from.len() == count - i);
+        body_invariant!(to.len() == to_len + i);
+        body_invariant!(to_len + count <= VEC_CAPACITY);
+        body_invariant!(forall(|x: usize| x < from.len() ==> from.index(x).index() == old(from.index(count - 1 - x).index())));
+        body_invariant!(forall(|x: usize| x < to_len ==> to.index(x).snapshot() == old(to.index(x).snapshot())));
+        body_invariant!(forall(|x: usize| x < i ==> to.index(to_len + x).buffer().index() == old(from.index(x).index())));
+        body_invariant!(forall(|x: usize| to_len <= x && x < to.len() && to.index(x).buffer().index() < pool.num_buffers() ==>
+            to.index(x).model() == mempool_model(pool, to.index(x).buffer())));
+
+        if let Some(buffer) = from.pop() {
+            to.push(OwnedPacketBuffer::from_pool(pool, buffer));
+        }
+        i += 1;
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::dma::{DescriptorRing, HeapDma, HeapDmaAllocator};
+    use crate::structs::{RX_STATUS_DD, RX_STATUS_EOP, RX_WB_PKT_LEN_START};
+    use crate::structs::tests::{pool, rx_regs, tx_regs};
+
+    const NUM_DESCS: usize = 8;
+
+    #[test]
+    fn rx_batch_wraps_the_received_buffers() {
+        let mut pool = pool(NUM_DESCS + 3, 64);
+        let mut rx_descs = DescriptorRing::<AdvancedRxDescriptor, HeapDma>::new(&mut HeapDmaAllocator, NUM_DESCS).unwrap();
+        let mut rx_bufs_in_use: RingBuf<PacketBuffer, NUM_DESCS> = RingBuf::new();
+        for _ in 0..NUM_DESCS {
+            rx_bufs_in_use.push_back(pool.pop().unwrap());
+        }
+        let in_ring: [usize; NUM_DESCS] = core::array::from_fn(|i| rx_bufs_in_use.index(i).index());
+        let mut regs = rx_regs();
+        let mut rx_cur = 0;
+        let mut buffers = VecWrapper::new();
+        for (i, desc) in rx_descs.descs_mut().iter_mut().take(5).enumerate() {
+            let metadata = (100 + i as u64) << RX_WB_PKT_LEN_START | (RX_STATUS_DD | RX_STATUS_EOP) as u64;
+            desc.header_buffer_address.write(metadata);
+        }
+
+        let received = rx_batch(rx_descs.descs_mut(), &mut rx_cur, &mut rx_bufs_in_use, &mut regs, NUM_DESCS as u16, &mut buffers, 2, &mut pool);
+        assert_eq!((received, rx_cur), (Ok(2), 2));
+        // the pool runs out after one more packet, which is still handed over
+        assert_eq!(rx_batch(rx_descs.descs_mut(), &mut rx_cur, &mut rx_bufs_in_use, &mut regs, NUM_DESCS as u16, &mut buffers, NUM_DESCS, &mut pool), Err(()));
+        assert_eq!((buffers.len(), rx_cur, pool.free_count()), (3, 3, 0));
+        for (i, index) in in_ring.into_iter().take(3).enumerate() {
+            let buffer = buffers.index(i);
+            assert_eq!((buffer.buffer().index(), buffer.length), (index, 100 + i as u16));
+            assert_eq!(buffer.model(), mempool_model(&pool, buffer.buffer()));
+            assert_eq!(buffer.phys_addr().value(), pool.data_paddr(buffer.buffer()).value());
+        }
+    }
+
+    #[test]
+    fn tx_batch_sends_with_the_length_of_the_wrapped_buffers() {
+        let mut pool = pool(2 * NUM_DESCS, 0);
+        let mut tx_descs = DescriptorRing::<AdvancedTxDescriptor, HeapDma>::new(&mut HeapDmaAllocator, NUM_DESCS).unwrap();
+        let mut tx_bufs_in_use: RingBuf<PacketBuffer, NUM_DESCS> = RingBuf::new();
+        let mut regs = tx_regs();
+        let (mut tx_clean, mut tx_cur) = (0, 0);
+        let mut buffers = VecWrapper::new();
+        let mut used_buffers = VecWrapper::new();
+        for i in 0..NUM_DESCS + 1 {
+            let buffer = pool.pop().unwrap();
+            let mut buffer = OwnedPacketBuffer::from_pool(&pool, buffer);
+            buffer.length = 60 + i as u16;
+            buffers.push(buffer);
+        }
+        let queued: [usize; NUM_DESCS + 1] = core::array::from_fn(|i| buffers.index(i).buffer().index());
+
+        let sent = tx_batch(
+            tx_descs.descs_mut(), &mut tx_bufs_in_use, NUM_DESCS as u16, &mut tx_clean, &mut tx_cur, &mut regs,
+            NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool
+        );
+        // the ring only has room for one descriptor less than its size, and sends from the back of `buffers`
+        assert_eq!((sent, tx_cur, regs.regs.ptr.tdt.read()), (Ok((NUM_DESCS as u16 - 1, 0)), 7, 7));
+        for i in 0..NUM_DESCS - 1 {
+            let buffer = tx_bufs_in_use.index(i);
+            let desc = &tx_descs.descs()[i];
+            assert_eq!(buffer.index(), queued[NUM_DESCS - i]);
+            assert_eq!(mempool_model(&pool, buffer), (desc.packet_buffer_address.read() as usize, desc.data_len.read()));
+            assert_eq!(desc.data_len.read(), 60 + (NUM_DESCS - i) as u16);
+        }
+        // the ones that weren't sent stay in order
+        assert_eq!((buffers.len(), buffers.index(0).buffer().index(), buffers.index(1).buffer().index()), (2, queued[0], queued[1]));
+
+        // a full ring isn't an error for the legacy function
+        let sent = tx_batch(
+            tx_descs.descs_mut(), &mut tx_bufs_in_use, NUM_DESCS as u16, &mut tx_clean, &mut tx_cur, &mut regs,
+            NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool
+        );
+        assert_eq!((sent, buffers.len()), (Ok((0, 0)), 2));
+
+        regs.regs.ptr.tdh.write(NUM_DESCS as u32);
+        let sent = tx_batch(
+            tx_descs.descs_mut(), &mut tx_bufs_in_use, NUM_DESCS as u16, &mut tx_clean, &mut tx_cur, &mut regs,
+            NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool
+        );
+        assert!(sent.is_err());
+        assert_eq!((buffers.len(), tx_bufs_in_use.len(), used_buffers.len()), (2, NUM_DESCS - 1, 0));
+    }
+}
@@ -1,28 +1,58 @@
-// works with command line release versions, but not with the vscode extension
-extern crate prusti_contracts;
-extern crate core;
-extern crate alloc;
-
-
-use prusti_contracts::*;
-// mod vector_spec;
-// mod structs;
-mod option_spec;
-mod result_spec;
-// mod tx_rx;
-// mod filter;
+//! Driver functions for the Intel 82599 NIC, verified with Prusti.
+//!
+//! The same tree builds with plain `cargo build` and verifies with `cargo prusti --features verify`.
+//! Ghost items which only exist for specifications are gated with `cfg(prusti)`, which prusti-rustc sets.
+//!
+//! Verification runs with overflow checks (see `Prusti.toml`), so besides the contracts it proves that the verified
+//! function bodies can't panic: no arithmetic overflows, out-of-bounds indexing, failed assertions or unwrapping of `None`.
+//! Ring positions are advanced modulo the ring size, and sums that can go past the end of a ring are computed in `usize`.
+//!
+//! The bodies of `#[trusted]` functions are assumed to meet their contracts, and some of them can still panic:
+//! * The accessors of the buffer metadata of a `Mempool` index it with the index of the `PacketBuffer`,
+//!   and `Mempool::offset` asserts that it is in bounds. The specifications don't tie a `PacketBuffer`
+//!   to the pool it came from, so passing a buffer of a larger pool panics.
+//! * `Mempool::refcount` is a trusted getter: nothing checks that the count matches the handles of a buffer
+//!   that are in use, so the reference counting relies on the preconditions of the functions that free buffers.
+//! * `FixedVec` panics when it is full, which the capacity preconditions of `VecWrapper` rule out.
+//! * `RingBuf` and `FixedVec` reach `unreachable!()` if a slot their invariants say is occupied is empty.
+//! * With the `legacy` feature, the bodies of `rx_batch` and `tx_batch` in [`tx_rx_old`] are trusted too,
+//!   because they were verified without overflow checks. Their `u16` position counters can overflow.
+//!
+//! Features:
+//! * `std` (default): host-side helpers for running the driver functions outside of Theseus, e.g. [`dma::HeapDmaAllocator`].
+//!   Implies `alloc`.
+//! * `alloc`: heap-backed storage. Without it, `VecWrapper` and the mempool metadata
+//!   use fixed-capacity storage, so the crate doesn't need an allocator.
+//! * `legacy`: the older functions on `PacketBufferS`, in [`structs_old`] and [`tx_rx_old`],
+//!   and [`legacy_adapter`], which runs code written against them on top of a `Mempool`.
+//! * `verify`: builds the specifications instead of erasing them. Only works when compiling with prusti-rustc.
 
-// use vector_spec::*;
-// use structs::*;
-use option_spec::*;
-use result_spec::*;
-
-// #[ensures(result == pages.len() - 1)]
-// #[ensures(0 <= result && result < pages.len())]
-// fn test(mut pages: &mut VecWrapper<i32>, elem: i32) -> usize {
-//     pages.push(elem);
-//     pages.len() - 1 
-// }
+#![no_std]
 
+extern crate prusti_contracts;
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
-fn main(){}
\ No newline at end of file
+pub use verified_specs::vector_spec;
+pub mod ring_buf;
+pub mod bits;
+mod layout;
+pub mod volatile;
+pub mod dma;
+pub mod structs;
+pub mod mempool_cache;
+pub mod step_counter;
+pub mod tx_rx;
+pub mod filter;
+pub mod error;
+// The legacy modules are kept as they were first verified, so lints that came later aren't fixed in them,
+// and they keep their unused items and spec-only variables.
+#[cfg(feature = "legacy")]
+#[allow(clippy::identity_op, clippy::unnecessary_cast, dead_code, unused_imports, unused_variables)]
+pub mod structs_old;
+#[cfg(feature = "legacy")]
+#[allow(clippy::result_unit_err, dead_code, unused_imports, unused_variables)]
+pub mod tx_rx_old;
+#[cfg(feature = "legacy")]
+#[allow(clippy::result_unit_err)]
+pub mod legacy_adapter;
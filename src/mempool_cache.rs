@@ -0,0 +1,198 @@
+//! A per-core cache of free buffers in front of a shared `Mempool`, similar to DPDK's mempool cache.
+//!
+//! Each core allocates from and frees to its own cache without any synchronization,
+//! and only touches the shared pool (which the caller has to lock) to refill an empty cache
+//! or to flush a cache that has grown past its flush threshold, moving many buffers at once.
+//!
+//! `PacketBuffer` can't be copied or cloned, and the buffers in a cache are free, so they have no other handles
+//! from `Mempool::clone_ref`. A free buffer is therefore always owned by exactly one cache or the pool.
+//! The specs additionally show that refilling and flushing conserve the total number of free buffers.
+
+use prusti_contracts::*;
+use crate::structs::{Mempool, PacketBuffer};
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{option_spec::*, vector_spec::VEC_CAPACITY};
+use verified_specs::vector_spec::VecWrapper;
+use crate::dma::DmaMemory;
+
+#[invariant(self.size < self.flush_threshold)]
+#[invariant(self.buffers.len() < self.flush_threshold)]
+#[invariant(self.flush_threshold <= VEC_CAPACITY)]
+pub struct MempoolCache {
+    /// The free buffers owned by this cache
+    buffers: VecWrapper<PacketBuffer>,
+    /// The number of buffers that an empty cache is refilled with, and that a full cache is flushed down to
+    size: usize,
+    /// The number of buffers at which the cache is flushed back to the pool
+    flush_threshold: usize,
+}
+
+impl MempoolCache {
+    #[requires(size > 0 && size < flush_threshold && flush_threshold <= VEC_CAPACITY)]
+    #[ensures(result.len() == 0)]
+    pub fn new(size: usize, flush_threshold: usize) -> MempoolCache {
+        MempoolCache { buffers: VecWrapper::new(), size, flush_threshold }
+    }
+
+    /// The number of free buffers in the cache.
+    #[pure]
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Takes a buffer from the cache, refilling it from `pool` first if it is empty.
+    /// Returns `None` only if both the cache and the pool are empty.
+    /// Like [`Mempool::pop`], the returned handle is the only reference to the buffer,
+    /// which holds an empty packet after the default headroom, whatever it held when it was freed to the cache.
+    #[ensures(result.is_some() ==> self.len() + pool.free_count() == old(self.len() + pool.free_count()) - 1)]
+    #[ensures(result.is_some() ==> pool.refcount(peek_option_ref(&result)) == 1)]
+    #[ensures(result.is_some() ==> pool.headroom(peek_option_ref(&result)) == pool.default_headroom())]
+    #[ensures(result.is_some() ==> pool.get_length(peek_option_ref(&result)) == 0)]
+    #[ensures(result.is_none() ==> self.len() == 0 && pool.free_count() == 0)]
+    pub fn alloc<M: DmaMemory>(&mut self, pool: &mut Mempool<M>) -> Option<PacketBuffer> {
+        if self.buffers.len() == 0 {
+            let size = self.size;
+            self.refill(pool, size);
+        }
+        let buffer = self.buffers.pop();
+        if let Some(ref b) = buffer {
+            pool.reset_metadata(b);
+        }
+        buffer
+    }
+
+    /// Returns a buffer to the cache, flushing the cache down to its size if it reaches the flush threshold.
+    /// `buffer` has to be the last reference to it, see [`Mempool::push`],
+    /// and the pool can't have more buffers than are free in it and in this cache.
+    #[requires(pool.refcount(&buffer) == 1)]
+    #[requires(self.len() + pool.free_count() < pool.num_buffers())]
+    #[ensures(self.len() + pool.free_count() == old(self.len() + pool.free_count()) + 1)]
+    pub fn free<M: DmaMemory>(&mut self, buffer: PacketBuffer, pool: &mut Mempool<M>) {
+        pool.mark_free(&buffer);
+        self.buffers.push(buffer);
+        if self.buffers.len() >= self.flush_threshold {
+            let excess = self.buffers.len() - self.size;
+            self.flush(pool, excess);
+        }
+    }
+
+    /// Moves up to `n` buffers from `pool` into the cache, without going over the cache size.
+    /// Returns the number of buffers that were moved.
+    #[ensures(result <= n)]
+    #[ensures(self.len() == old(self.len()) + result)]
+    #[ensures(pool.free_count() == old(pool.free_count()) - result)]
+    #[ensures((result < n && self.len() < self.size) ==> pool.free_count() == 0)]
+    pub fn refill<M: DmaMemory>(&mut self, pool: &mut Mempool<M>, n: usize) -> usize {
+        let mut moved = 0;
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let len = self.buffers.len();
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let free_count = pool.free_count();
+
+        while moved < n && self.buffers.len() < self.size {
+            body_invariant!(moved < n);
+            body_invariant!(self.buffers.len() < self.size);
+            body_invariant!(self.buffers.len() == len + moved);
+            body_invariant!(pool.free_count() == free_count - moved);
+
+            match pool.pop() {
+                Some(buffer) => self.buffers.push(buffer),
+                None => break,
+            }
+            moved += 1;
+        }
+        moved
+    }
+
+    /// Moves up to `n` buffers from the cache back to `pool`.
+    /// Returns the number of buffers that were moved.
+    #[requires(self.len() + pool.free_count() <= pool.num_buffers())]
+    #[ensures(result <= n)]
+    #[ensures(old(self.len()) >= n ==> result == n)]
+    #[ensures(self.len() == old(self.len()) - result)]
+    #[ensures(pool.free_count() == old(pool.free_count()) + result)]
+    pub fn flush<M: DmaMemory>(&mut self, pool: &mut Mempool<M>, n: usize) -> usize {
+        let mut moved = 0;
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let len = self.buffers.len();
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let free_count = pool.free_count();
+
+        while moved < n {
+            body_invariant!(moved < n);
+            body_invariant!(self.buffers.len() == len - moved);
+            body_invariant!(pool.free_count() == free_count + moved);
+            body_invariant!(self.buffers.len() + pool.free_count() <= pool.num_buffers());
+
+            match self.buffers.pop() {
+                Some(buffer) => pool.push_free(buffer),
+                None => break,
+            }
+            moved += 1;
+        }
+        moved
+    }
+
+    /// Returns all buffers of the cache to `pool`, e.g. before the core stops using the pool.
+    #[requires(self.len() + pool.free_count() <= pool.num_buffers())]
+    #[ensures(self.len() == 0)]
+    #[ensures(pool.free_count() == old(pool.free_count()) + old(self.len()))]
+    pub fn drain<M: DmaMemory>(&mut self, pool: &mut Mempool<M>) {
+        let len = self.buffers.len();
+        self.flush(pool, len);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::structs::tests::pool;
+
+    #[test]
+    fn refill_and_flush() {
+        let mut pool = pool(8, 0);
+        let mut cache = MempoolCache::new(2, 4);
+
+        let a = cache.alloc(&mut pool).unwrap();
+        assert_eq!((cache.len(), pool.free_count()), (1, 6));
+        let b = cache.alloc(&mut pool).unwrap();
+        let c = cache.alloc(&mut pool).unwrap();
+        assert_eq!((cache.len(), pool.free_count()), (1, 4));
+
+        cache.free(a, &mut pool);
+        cache.free(b, &mut pool);
+        assert_eq!((cache.len(), pool.free_count()), (3, 4));
+        // reaching the flush threshold flushes the cache down to its size
+        cache.free(c, &mut pool);
+        assert_eq!((cache.len(), pool.free_count()), (2, 6));
+
+        cache.drain(&mut pool);
+        assert_eq!((cache.len(), pool.free_count()), (0, 8));
+    }
+
+    #[test]
+    fn alloc_from_an_empty_pool() {
+        let mut pool = pool(1, 0);
+        let mut cache = MempoolCache::new(2, 4);
+        let a = cache.alloc(&mut pool).unwrap();
+        assert!(cache.alloc(&mut pool).is_none());
+        cache.free(a, &mut pool);
+        // a buffer in the cache is free, so it has no reference
+        assert_eq!(pool.refcount(cache.buffers.index(0)), 0);
+        assert!(cache.alloc(&mut pool).is_some());
+    }
+
+    #[test]
+    fn metadata_is_reset_on_alloc() {
+        let mut pool = pool(1, 32);
+        let mut cache = MempoolCache::new(1, 2);
+        let b = cache.alloc(&mut pool).unwrap();
+        assert!(pool.prepend(&b, 32));
+        assert!(pool.append(&b, 10));
+        cache.free(b, &mut pool);
+
+        let b = cache.alloc(&mut pool).unwrap();
+        assert_eq!((pool.headroom(&b), pool.get_length(&b), pool.refcount(&b)), (32, 0, 1));
+    }
+}
@@ -0,0 +1,218 @@
+//! A fixed-capacity FIFO queue which lives inline instead of on the heap.
+//!
+//! The queues use it for the buffers currently owned by the NIC, whose number is bounded by the size of the descriptor ring.
+//! Elements are pushed at the back and popped from the front in constant time, so cleaning transmitted buffers
+//! doesn't shift the remaining ones like draining the front of a `Vec` does.
+//!
+//! Like `VecWrapper`, the contents are specified by a ghost `view()`, the sequence of the snapshots of the elements
+//! from front to back. `index(i)` is the `i`th element counted from the front.
+//!
+//! The position arithmetic and the length updates are verified. Only the accesses to the `Option` slots are trusted,
+//! together with `slots_view()`, the snapshots of the slots, which `view()` is tied to, because the invariant doesn't
+//! state which slots are occupied.
+
+use prusti_contracts::*;
+use verified_specs::snapshot::Snapshot;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{option_spec::*, seq_spec::*};
+
+#[invariant(self.len <= N)]
+#[invariant(N == 0 || self.head < N)]
+pub struct RingBuf<T, const N: usize> {
+    /// The slots of the ring, of which the `len` slots starting at `head` (wrapping around) are occupied
+    slots: [Option<T>; N],
+    /// The slot of the front element
+    head: usize,
+    /// The number of elements
+    len: usize,
+}
+
+impl<T, const N: usize> RingBuf<T, N> {
+    #[requires(N > 0)]
+    #[ensures(result.len() == 0)]
+    pub fn new() -> Self {
+        RingBuf { slots: empty_slots(), head: 0, len: 0 }
+    }
+
+    /// The maximum number of elements.
+    #[pure]
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    #[pure]
+    #[ensures(result <= N)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[pure]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[pure]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// The slot of position `index`, counted from the front and wrapping around at the end of the slots.
+    #[pure]
+    #[requires(index < N)]
+    #[ensures(result < N)]
+    fn slot(&self, index: usize) -> usize {
+        if index < N - self.head { self.head + index } else { index - (N - self.head) }
+    }
+
+    /// Returns the element at position `index`, counted from the front.
+    /// Trusted only because the invariant doesn't state which slots are occupied.
+    #[trusted]
+    #[pure]
+    #[requires(index < self.len())]
+    pub fn index(&self, index: usize) -> &T {
+        match &self.slots[self.slot(index)] {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<T: Snapshot, const N: usize> RingBuf<T, N> {
+    /// The snapshots of the values in the slots, in slot order. The snapshot of a free slot is unspecified.
+    /// Only exists for specifications.
+    #[cfg(prusti)]
+    #[pure]
+    #[trusted]
+    #[ensures(result.len() == Int::new_usize(N))]
+    #[ensures(forall(|i: usize| i < self.len() ==> result[self.slot(i)] == self.index(i).snapshot()))]
+    fn slots_view(&self) -> Seq<T::Model> {
+        unimplemented!()
+    }
+
+    /// The snapshots of the elements, from front to back. Only exists for specifications.
+    #[cfg(prusti)]
+    #[pure]
+    #[trusted]
+    #[ensures(result.len() == Int::new_usize(self.len()))]
+    #[ensures(forall(|i: usize| i < self.len() ==> result[i] == self.index(i).snapshot()))]
+    #[ensures(forall(|i: usize| i < self.len() ==> result[i] == self.slots_view()[self.slot(i)]))]
+    pub fn view(&self) -> Seq<T::Model> {
+        unimplemented!()
+    }
+
+    #[trusted]
+    #[requires(index < self.len())]
+    #[ensures(result.snapshot() == old(self.index(index).snapshot()))]
+    #[after_expiry(self.len() == old(self.len()))]
+    #[after_expiry(self.view() == seq_update(old(self.view()), index, before_expiry(result).snapshot()))]
+    pub fn index_mut(&mut self, index: usize) -> &mut T {
+        let slot = self.slot(index);
+        match &mut self.slots[slot] {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+
+    /// Appends `value` at the back.
+    #[requires(!self.is_full())]
+    #[ensures(self.len() == old(self.len()) + 1)]
+    #[ensures(self.view() == seq_push(old(self.view()), old(value.snapshot())))]
+    pub fn push_back(&mut self, value: T) {
+        let tail = self.slot(self.len);
+        self.fill(tail, value);
+        self.len += 1;
+    }
+
+    /// Removes the element at the front, or returns `None` if the queue is empty.
+    #[ensures(result.is_none() == (old(self.len()) == 0))]
+    #[ensures(result.is_none() ==> self.len() == old(self.len()))]
+    #[ensures(result.is_some() ==> self.len() == old(self.len()) - 1)]
+    #[ensures(result.is_some() ==> old(self.view()) == Seq::single(peek_option_ref(&result).snapshot()).concat(self.view()))]
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.take(self.head);
+        self.head = if self.head + 1 < N { self.head + 1 } else { 0 };
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Stores `value` in the free slot `slot`.
+    #[trusted]
+    #[requires(slot < N)]
+    #[ensures(self.head == old(self.head) && self.len == old(self.len))]
+    #[ensures(self.slots_view() == seq_update(old(self.slots_view()), slot, old(value.snapshot())))]
+    fn fill(&mut self, slot: usize, value: T) {
+        self.slots[slot] = Some(value);
+    }
+
+    /// Moves the value out of the occupied slot `slot`, which becomes free.
+    #[trusted]
+    #[requires(slot < N)]
+    #[ensures(self.head == old(self.head) && self.len == old(self.len))]
+    #[ensures(result.snapshot() == old(self.slots_view())[slot])]
+    #[ensures(self.slots_view() == old(self.slots_view()))]
+    fn take(&mut self, slot: usize) -> T {
+        match self.slots[slot].take() {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+}
+
+#[trusted]
+fn empty_slots<T, const N: usize>() -> [Option<T>; N] {
+    core::array::from_fn(|_| None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_order_across_the_wraparound() {
+        let mut ring: RingBuf<u32, 3> = RingBuf::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.capacity(), 3);
+
+        for round in 0..4 {
+            ring.push_back(round * 10);
+            ring.push_back(round * 10 + 1);
+            assert_eq!(*ring.index(0), round * 10);
+            assert_eq!(*ring.index(1), round * 10 + 1);
+            assert_eq!(ring.pop_front(), Some(round * 10));
+            assert_eq!(ring.pop_front(), Some(round * 10 + 1));
+        }
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop_front(), None);
+    }
+
+    #[test]
+    fn single_slot() {
+        let mut ring: RingBuf<u32, 1> = RingBuf::new();
+        for value in 0..3 {
+            ring.push_back(value);
+            assert!(ring.is_full());
+            assert_eq!(*ring.index(0), value);
+            assert_eq!(ring.pop_front(), Some(value));
+        }
+    }
+
+    #[test]
+    fn full() {
+        let mut ring: RingBuf<u32, 2> = RingBuf::new();
+        ring.push_back(1);
+        assert!(!ring.is_full());
+        ring.push_back(2);
+        assert!(ring.is_full());
+        assert_eq!(ring.len(), 2);
+
+        assert_eq!(ring.pop_front(), Some(1));
+        ring.push_back(3);
+        assert!(ring.is_full());
+        *ring.index_mut(1) += 1;
+        assert_eq!((*ring.index(0), *ring.index(1)), (2, 4));
+    }
+}
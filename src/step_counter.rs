@@ -0,0 +1,68 @@
+//! Counters of the work done by the batch functions in [`crate::tx_rx`], so that their worst case
+//! can be stated in their postconditions.
+//!
+//! Prusti has no ghost state that lives across calls, so the counters are ordinary runtime state: they take two words
+//! in every queue and each counted step costs an increment. They only ever go up and nothing in the driver reads them,
+//! so they don't change what it does. Callers can read them at runtime too, e.g. to monitor the work done per call.
+//! They accumulate across calls, so a caller can reset them at the start of a period and bound the work of the whole period.
+
+use prusti_contracts::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepCounter {
+    /// The number of loop iterations, each of which looks at one descriptor or one buffer
+    iterations: usize,
+    /// The number of writes to device registers
+    register_writes: usize,
+}
+
+impl StepCounter {
+    #[ensures(result.iterations() == 0 && result.register_writes() == 0)]
+    pub fn new() -> StepCounter {
+        StepCounter { iterations: 0, register_writes: 0 }
+    }
+
+    #[pure]
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    #[pure]
+    pub fn register_writes(&self) -> usize {
+        self.register_writes
+    }
+
+    /// Whether at most `bound` iterations were counted since the counter stood at `start`.
+    #[pure]
+    pub fn iterations_within(&self, start: usize, bound: usize) -> bool {
+        self.iterations >= start && self.iterations - start <= bound
+    }
+
+    /// Whether at most `bound` register writes were counted since the counter stood at `start`.
+    #[pure]
+    pub fn register_writes_within(&self, start: usize, bound: usize) -> bool {
+        self.register_writes >= start && self.register_writes - start <= bound
+    }
+
+    /// Counts one loop iteration. The counters saturate instead of overflowing, so the bounds hold either way.
+    #[ensures(self.iterations_within(old(self.iterations()), 1))]
+    #[ensures(self.register_writes() == old(self.register_writes()))]
+    pub fn count_iteration(&mut self) {
+        if self.iterations < usize::MAX {
+            self.iterations += 1;
+        }
+    }
+
+    #[ensures(self.register_writes_within(old(self.register_writes()), 1))]
+    #[ensures(self.iterations() == old(self.iterations()))]
+    pub fn count_register_write(&mut self) {
+        if self.register_writes < usize::MAX {
+            self.register_writes += 1;
+        }
+    }
+
+    #[ensures(self.iterations() == 0 && self.register_writes() == 0)]
+    pub fn reset(&mut self) {
+        *self = StepCounter::new();
+    }
+}
@@ -1,34 +1,66 @@
 use prusti_contracts::*;
 use core::ops::{DerefMut, Deref};
 
-use crate::vector_spec::VecWrapper;
+use verified_specs::vector_spec::{VecWrapper, VEC_CAPACITY};
+use verified_specs::snapshot::Snapshot;
+use crate::bits::*;
+use crate::layout::{register_layout, bitfields};
+use crate::dma::{DmaMemory, DmaAllocator};
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{result_spec::*, option_spec::*};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(not(feature = "alloc"))]
+use verified_specs::{fixed_vec::FixedVec, vector_spec::FIXED_CAPACITY};
+
+pub use crate::volatile::Volatile;
 
-pub struct Volatile<T: Copy>{
-    inner: T
-}
 
-impl<T: Copy> Volatile<T> {
-    pub fn write(&mut self, val: T) {
-        self.inner = val;
-    }
+/// Rx Status: Descriptor Done
+pub const RX_STATUS_DD:                    u8 = 1 << 0;
+/// Rx Status: End of Packet
+pub const RX_STATUS_EOP:                   u8 = 1 << 1;
 
-    pub fn read(&self) -> T {
-        self.inner
+/// Rx write-back: Extended Status, occupies bits `[19:0]` of the upper 64 bits of the descriptor
+pub const RX_WB_EXT_STATUS_START:          u32 = 0;
+pub const RX_WB_EXT_STATUS_WIDTH:          u32 = 20;
+/// Rx write-back: Packet Length, occupies bits `[47:32]` of the upper 64 bits of the descriptor
+pub const RX_WB_PKT_LEN_START:             u32 = 32;
+pub const RX_WB_PKT_LEN_WIDTH:             u32 = 16;
+
+register_layout! {
+    pub struct AdvancedRxDescriptor, base 0x0, size 16 {
+        /// Starting physcal address of the receive buffer for the packet.
+        0x0 => pub packet_buffer_address:  Volatile<u64>,
+        /// Starting physcal address of the receive buffer for the header.
+        /// This field will only be used if header splitting is enabled. 
+        0x8 => pub header_buffer_address:  Volatile<u64>,
     }
 }
 
+bitfields! {
+    impl AdvancedRxDescriptor {
+        /// Write Back mode function for the Advanced Receive Descriptor.
+        /// Status information indicates whether a descriptor has been used 
+        /// and whether the buffer is the last one for a packet
+        pub get_ext_status: header_buffer_address, u64, bits(RX_WB_EXT_STATUS_START, RX_WB_EXT_STATUS_WIDTH);
 
-/// Rx Status: Descriptor Done
-pub const RX_STATUS_DD:                    u8 = 1 << 0;
-/// Rx Status: End of Packet
-pub const RX_STATUS_EOP:                   u8 = 1 << 1;
+        /// Write Back mode function for the Advanced Receive Descriptor.
+        /// Returns the number of bytes posted to the packet buffer
+        pub get_pkt_len: header_buffer_address, u64, bits(RX_WB_PKT_LEN_START, RX_WB_PKT_LEN_WIDTH);
+    }
+}
+
+/// The snapshot of a descriptor is made of the values software last wrote to its fields.
+#[cfg(prusti)]
+impl Snapshot for AdvancedRxDescriptor {
+    type Model = (u64, u64);
 
-pub struct AdvancedRxDescriptor {
-    /// Starting physcal address of the receive buffer for the packet.
-    pub packet_buffer_address:  Volatile<u64>,
-    /// Starting physcal address of the receive buffer for the header.
-    /// This field will only be used if header splitting is enabled. 
-    pub header_buffer_address:  Volatile<u64>,
+    #[pure]
+    fn snapshot(&self) -> (u64, u64) {
+        (self.packet_buffer_address.model(), self.header_buffer_address.model())
+    }
 }
 
 impl AdvancedRxDescriptor {
@@ -39,6 +71,8 @@ impl AdvancedRxDescriptor {
     // }
 
     #[inline(always)]
+    #[ensures(self.packet_buffer_address.model() == packet_buffer_address.value() as u64)]
+    #[ensures(self.header_buffer_address.model() == 0)]
     pub(crate) fn set_packet_address(&mut self, packet_buffer_address: PhysicalAddress) {
         self.packet_buffer_address.write(packet_buffer_address.value() as u64);
         self.header_buffer_address.write(0);
@@ -48,103 +82,659 @@ impl AdvancedRxDescriptor {
     #[inline(always)]
     pub fn rx_metadata(&self) -> (bool, u16) {
         let metadata = self.header_buffer_address.read();
-        ((metadata & RX_STATUS_DD as u64) == RX_STATUS_DD as u64, (metadata >> 32) as u16 & 0xFFFF)
+        let status = get_bits_u64(metadata, RX_WB_EXT_STATUS_START, RX_WB_EXT_STATUS_WIDTH);
+        let length = get_bits_u64(metadata, RX_WB_PKT_LEN_START, RX_WB_PKT_LEN_WIDTH);
+        ((status & RX_STATUS_DD as u64) == RX_STATUS_DD as u64, length as u16)
     }
 
     #[inline(always)]
-    pub(crate) fn reset_status(&mut self) {
-        self.header_buffer_address.write(0);
+    pub fn descriptor_done(&self) -> bool{
+        (self.get_ext_status() & RX_STATUS_DD as u64) == RX_STATUS_DD as u64
+    }
+
+    #[inline(always)]
+    pub fn end_of_packet(&self) -> bool {
+        (self.get_ext_status() & RX_STATUS_EOP as u64) == RX_STATUS_EOP as u64      
+    }
+
+    #[inline(always)]
+    #[ensures(result <= mask_u64(RX_WB_PKT_LEN_WIDTH))]
+    pub fn length(&self) -> u64 {
+        self.get_pkt_len()
+    }
+}
+
+/// A pool of packet buffers, each of which is one `EthernetFrame` sized slot of `mp`.
+/// The physical address of a buffer is derived from its index and the physical address of the mapping.
+///
+/// The packet data of a buffer doesn't have to start at the start of its slot: every allocated buffer starts with
+/// `headroom` bytes in front of its data, into which headers can be prepended without copying the packet.
+#[invariant(self.headroom <= MAX_HEADROOM)]
+#[invariant(self.num_buffers() <= VEC_CAPACITY)]
+#[invariant(self.free_count() <= self.num_buffers())]
+pub struct Mempool<M: DmaMemory = MappedPages> {
+    /// The free buffers, each of which has a reference count of 0
+    buffer_indices: VecWrapper<PacketBuffer>,
+    buffers: MetadataTable,
+    /// The physically contiguous memory that holds the frames of all buffers
+    mp: M,
+    /// The number of bytes in front of the packet data of a newly allocated buffer
+    headroom: usize,
+}
+
+impl<M: DmaMemory> Mempool<M> {
+    /// Allocates the memory for `num_buffers` buffers from `allocator` and creates a pool from it.
+    #[requires(num_buffers > 0 && num_buffers <= usize::MAX / ETHERNET_FRAME_SIZE)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).num_buffers() >= num_buffers)]
+    pub fn from_allocator<A: DmaAllocator<Memory = M>>(allocator: &mut A, num_buffers: usize) -> Result<Mempool<M>, &'static str> {
+        let (mp, _paddr) = allocator.alloc_dma(num_buffers * ETHERNET_FRAME_SIZE, PAGE_SIZE)?;
+        Mempool::new(mp)
+    }
+
+    /// Splits `mp` into `ETHERNET_FRAME_SIZE` slots, each of which becomes a free buffer of the pool without headroom.
+    /// Any memory at the end of `mp` that is smaller than a frame is left unused.
+    /// Returns an error if `mp` holds more buffers than the free list can hold, see [`VEC_CAPACITY`].
+    #[ensures(result.is_ok() == (old(mp.size_in_bytes()) / ETHERNET_FRAME_SIZE <= VEC_CAPACITY))]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).num_buffers() == old(mp.size_in_bytes()) / ETHERNET_FRAME_SIZE)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).free_count() == peek_result_ref(&result).num_buffers())]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).default_headroom() == 0)]
+    pub fn new(mp: M) -> Result<Mempool<M>, &'static str> {
+        Mempool::with_headroom(mp, 0)
+    }
+
+    /// Like [`Mempool::new`], but the packet data of every allocated buffer starts `headroom` bytes into its slot.
+    #[requires(headroom <= MAX_HEADROOM)]
+    #[ensures(result.is_ok() == (old(mp.size_in_bytes()) / ETHERNET_FRAME_SIZE <= VEC_CAPACITY))]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).num_buffers() == old(mp.size_in_bytes()) / ETHERNET_FRAME_SIZE)]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).free_count() == peek_result_ref(&result).num_buffers())]
+    #[ensures(result.is_ok() ==> peek_result_ref(&result).default_headroom() == headroom)]
+    pub fn with_headroom(mp: M, headroom: usize) -> Result<Mempool<M>, &'static str> {
+        let num_buffers = mp.size_in_bytes() / ETHERNET_FRAME_SIZE;
+        if num_buffers > VEC_CAPACITY {
+            return Err("the memory holds more buffers than a mempool can manage");
+        }
+        let buffers = new_buffer_metadata(num_buffers);
+        let mut buffer_indices = VecWrapper::new();
+
+        let mut i = 0;
+        while i < num_buffers {
+            body_invariant!(i < num_buffers && num_buffers <= VEC_CAPACITY);
+            body_invariant!(buffer_indices.len() == i);
+            body_invariant!(forall(|x: usize| 0 <= x && x < buffer_indices.len() ==> buffer_indices.index(x).index() < num_buffers));
+            buffer_indices.push(PacketBuffer(i));
+            i += 1;
+        }
+
+        Ok(Mempool { buffer_indices, buffers, mp, headroom })
+    }
+
+    /// The headroom of a newly allocated buffer.
+    #[pure]
+    pub fn default_headroom(&self) -> usize {
+        self.headroom
+    }
+
+    /// The number of bytes the NIC may write into a receive buffer, which starts after the headroom.
+    /// The receive buffer size of the queues (`SRRCTL.BSIZEPACKET`) must not be set any larger.
+    #[pure]
+    #[requires(self.default_headroom() <= ETHERNET_FRAME_SIZE)]
+    pub fn rx_buffer_size(&self) -> usize {
+        ETHERNET_FRAME_SIZE - self.headroom
+    }
+
+    #[pure]
+    pub fn num_buffers(&self) -> usize {
+        self.mp.size_in_bytes() / ETHERNET_FRAME_SIZE
+    }
+
+    /// The physical address of the frame of the buffer with `index`.
+    /// Each address lies inside the mapping, and the frames of two different indices don't overlap.
+    #[pure]
+    #[requires(index < self.num_buffers())]
+    #[ensures(result >= self.mp.start_paddr().value())]
+    #[ensures(result + ETHERNET_FRAME_SIZE <= self.mp.start_paddr().value() + self.mp.size_in_bytes())]
+    #[ensures(forall(|other: usize| (other < self.num_buffers() && other != index) ==> 
+        (result + ETHERNET_FRAME_SIZE <= self.slot_paddr_unchecked(other) || self.slot_paddr_unchecked(other) + ETHERNET_FRAME_SIZE <= result)
+    ))]
+    pub fn slot_paddr(&self, index: usize) -> usize {
+        self.slot_paddr_unchecked(index)
+    }
+
+    #[pure]
+    #[requires(index < self.num_buffers())]
+    fn slot_paddr_unchecked(&self, index: usize) -> usize {
+        self.mp.start_paddr().value() + index * ETHERNET_FRAME_SIZE
+    }
+
+    /// The number of buffers that are currently free in the pool.
+    #[pure]
+    pub fn free_count(&self) -> usize {
+        self.buffer_indices.len()
+    }
+
+    /// Takes a free buffer from the pool. The returned handle is the only reference to the buffer,
+    /// which holds an empty packet after the default headroom.
+    #[ensures(result.is_some() ==> self.free_count() == old(self.free_count()) - 1)]
+    #[ensures(result.is_some() ==> self.refcount(peek_option_ref(&result)) == 1)]
+    #[ensures(result.is_some() ==> self.headroom(peek_option_ref(&result)) == self.default_headroom())]
+    #[ensures(result.is_some() ==> self.get_length(peek_option_ref(&result)) == 0)]
+    #[ensures(result.is_none() ==> self.free_count() == old(self.free_count()) && self.free_count() == 0)]
+    pub fn pop(&mut self) -> Option<PacketBuffer> {
+        let buffer = self.buffer_indices.pop();
+        if let Some(ref b) = buffer {
+            self.reset_metadata(b);
+        }
+        buffer
     }
 
+    /// Sets up the metadata of a buffer that is being allocated: `buffer` becomes its only reference,
+    /// and it holds an empty packet after the default headroom.
+    #[ensures(self.free_count() == old(self.free_count()))]
+    #[ensures(self.refcount(buffer) == 1)]
+    #[ensures(self.headroom(buffer) == self.default_headroom())]
+    #[ensures(self.get_length(buffer) == 0)]
+    pub(crate) fn reset_metadata(&mut self, buffer: &PacketBuffer) {
+        self.set_refcount(buffer, 1);
+        let headroom = self.headroom;
+        self.set_data(buffer, headroom, 0);
+    }
+
+    /// Returns a buffer to the pool. `buffer` has to be the last reference to it;
+    /// a buffer that may have been shared with [`Mempool::clone_ref`] is given back with [`Mempool::release`],
+    /// or with [`Mempool::release_ref`] followed by `push`.
+    #[requires(self.refcount(&buffer) == 1)]
+    #[ensures(self.free_count() == old(self.free_count()) + 1)]
+    pub fn push(&mut self, buffer: PacketBuffer) {
+        self.push_free(buffer)
+    }
+
+    /// Returns a buffer that is already known to be free to the pool, such as one that was held by a `MempoolCache`.
+    /// The free list has room for it as long as every buffer of the pool is free at most once.
+    #[requires(self.free_count() < self.num_buffers())]
+    #[ensures(self.free_count() == old(self.free_count()) + 1)]
+    pub(crate) fn push_free(&mut self, buffer: PacketBuffer) {
+        self.mark_free(&buffer);
+        self.buffer_indices.push(buffer)
+    }
+
+    /// Drops the last handle to `buffer` without returning it to the free list, for a `MempoolCache` which keeps it.
+    #[ensures(self.refcount(buffer) == 0)]
+    #[ensures(self.free_count() == old(self.free_count()))]
+    #[ensures(self.headroom(buffer) == old(self.headroom(buffer)))]
+    #[ensures(self.get_length(buffer) == old(self.get_length(buffer)))]
+    pub(crate) fn mark_free(&mut self, buffer: &PacketBuffer) {
+        self.set_refcount(buffer, 0);
+    }
+
+    /// Creates another handle to the buffer of `buffer`, so that it can e.g. be sent on several queues without copying it.
+    /// Returns `None` if the reference count is already at its maximum.
+    #[ensures(self.free_count() == old(self.free_count()))]
+    #[ensures(result.is_some() ==> peek_option_ref(&result).index() == buffer.index())]
+    #[ensures(result.is_some() ==> self.refcount(buffer) == old(self.refcount(buffer)) + 1)]
+    #[ensures(result.is_none() ==> self.refcount(buffer) == old(self.refcount(buffer)) && self.refcount(buffer) == u16::MAX)]
+    pub fn clone_ref(&mut self, buffer: &PacketBuffer) -> Option<PacketBuffer> {
+        let refcount = self.refcount(buffer);
+        if refcount == u16::MAX {
+            return None;
+        }
+        self.set_refcount(buffer, refcount + 1);
+        Some(PacketBuffer(buffer.0))
+    }
+
+    /// Drops the handle `buffer`. If it was the last reference, it is returned so that the caller can reuse
+    /// or free it, otherwise only the reference count is decremented.
+    /// A live handle always counts as a reference, so the returned handle has a reference count of 1.
+    #[ensures(self.free_count() == old(self.free_count()))]
+    #[ensures(result.is_some() == (old(self.refcount(&buffer)) <= 1))]
+    #[ensures(result.is_some() ==> self.refcount(peek_option_ref(&result)) == 1)]
+    #[ensures(result.is_some() ==> peek_option_ref(&result).index() == old(buffer.index()))]
+    #[ensures(result.is_none() ==> self.refcount(&buffer) == old(self.refcount(&buffer)) - 1)]
+    pub fn release_ref(&mut self, buffer: PacketBuffer) -> Option<PacketBuffer> {
+        let refcount = self.refcount(&buffer);
+        if refcount <= 1 {
+            self.set_refcount(&buffer, 1);
+            return Some(buffer);
+        }
+        self.set_refcount(&buffer, refcount - 1);
+        None
+    }
+
+    /// Drops the handle `buffer`, and returns the buffer to the pool if it was the last reference.
+    /// Returns whether the buffer was freed.
+    #[ensures(result == (old(self.refcount(&buffer)) <= 1))]
+    #[ensures(result ==> self.free_count() == old(self.free_count()) + 1)]
+    #[ensures(!result ==> self.free_count() == old(self.free_count()))]
+    #[ensures(!result ==> self.refcount(&buffer) == old(self.refcount(&buffer)) - 1)]
+    pub fn release(&mut self, buffer: PacketBuffer) -> bool {
+        match self.release_ref(buffer) {
+            Some(last) => {
+                self.push(last);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The number of handles to `buffer` that are in use, which is 0 for a free buffer,
+    /// whether it is in the free list of the pool or held by a `MempoolCache`.
+    /// That the count matches the handles is not verified, as nothing tracks the handles themselves:
+    /// it relies on handles only being created by `pop` and `clone_ref` and only dropped by `release_ref`
+    /// and the free paths, whose preconditions require the last reference.
+    /// The postcondition is trusted for the same reason: a buffer with a handle isn't in the free list,
+    /// so the free list has room for another buffer.
     #[inline(always)]
+    #[pure]
     #[trusted]
-    pub fn descriptor_done(&self) -> bool{
-        (self.get_ext_status() & RX_STATUS_DD as u64) == RX_STATUS_DD as u64
+    #[ensures(result != 0 ==> self.free_count() < self.num_buffers())]
+    pub fn refcount(&self, buffer: &PacketBuffer) -> u16 {
+        self.buffers[buffer.0].refcount
     }
 
     #[inline(always)]
     #[trusted]
-    pub fn end_of_packet(&self) -> bool {
-        (self.get_ext_status() & RX_STATUS_EOP as u64) == RX_STATUS_EOP as u64      
+    #[ensures(self.refcount(buffer) == refcount)]
+    #[ensures(self.free_count() == old(self.free_count()))]
+    #[ensures(self.headroom(buffer) == old(self.headroom(buffer)))]
+    #[ensures(self.get_length(buffer) == old(self.get_length(buffer)))]
+    fn set_refcount(&mut self, buffer: &PacketBuffer, refcount: u16) {
+        self.buffers[buffer.0].refcount = refcount;
     }
 
+    /// The number of bytes in front of the packet data in the frame of `buffer`.
+    /// Together with the packet data it fits in the frame, which `set_data` and [`Mempool::set_length`] maintain,
+    /// so the arithmetic on headroom and length can't overflow.
     #[inline(always)]
+    #[pure]
     #[trusted]
-    pub fn length(&self) -> u64 {
-        self.get_pkt_len() as u64
+    #[ensures(result + self.get_length(buffer) as usize <= ETHERNET_FRAME_SIZE)]
+    pub fn headroom(&self, buffer: &PacketBuffer) -> usize {
+        self.buffers[buffer.0].data_offset as usize
     }
 
-    /// Write Back mode function for the Advanced Receive Descriptor.
-    /// Status information indicates whether a descriptor has been used 
-    /// and whether the buffer is the last one for a packet
+    /// The number of bytes behind the packet data in the frame of `buffer`.
     #[inline(always)]
+    #[pure]
     #[trusted]
-    pub fn get_ext_status(&self) -> u64{
-        self.header_buffer_address.read() & 0xFFFFF //.get_bits(0..19) ?
+    #[ensures(self.headroom(buffer) + self.get_length(buffer) as usize + result == ETHERNET_FRAME_SIZE)]
+    pub fn tailroom(&self, buffer: &PacketBuffer) -> usize {
+        let metadata = &self.buffers[buffer.0];
+        ETHERNET_FRAME_SIZE - metadata.data_offset as usize - metadata.length as usize
+    }
+
+    /// Extends the packet data of `buffer` by `n` bytes at the front, e.g. to add an encapsulation header.
+    /// Returns `false` and leaves the buffer unchanged if there isn't enough headroom.
+    #[ensures(result == (n <= old(self.headroom(buffer))))]
+    #[ensures(result ==> self.headroom(buffer) == old(self.headroom(buffer)) - n)]
+    #[ensures(result ==> self.get_length(buffer) as usize == old(self.get_length(buffer)) as usize + n)]
+    #[ensures(!result ==> self.headroom(buffer) == old(self.headroom(buffer)) && self.get_length(buffer) == old(self.get_length(buffer)))]
+    #[ensures(self.refcount(buffer) == old(self.refcount(buffer)))]
+    pub fn prepend(&mut self, buffer: &PacketBuffer, n: usize) -> bool {
+        let headroom = self.headroom(buffer);
+        if n > headroom {
+            return false;
+        }
+        let length = self.get_length(buffer) as usize;
+        self.set_data(buffer, headroom - n, length + n);
+        true
     }
-    
-    
-    /// Write Back mode function for the Advanced Receive Descriptor.
-    /// Returns the number of bytes posted to the packet buffer
+
+    /// Extends the packet data of `buffer` by `n` bytes at the end.
+    /// Returns `false` and leaves the buffer unchanged if there isn't enough tailroom.
+    #[ensures(result == (n <= old(self.tailroom(buffer))))]
+    #[ensures(self.headroom(buffer) == old(self.headroom(buffer)))]
+    #[ensures(result ==> self.get_length(buffer) as usize == old(self.get_length(buffer)) as usize + n)]
+    #[ensures(!result ==> self.get_length(buffer) == old(self.get_length(buffer)))]
+    #[ensures(self.refcount(buffer) == old(self.refcount(buffer)))]
+    pub fn append(&mut self, buffer: &PacketBuffer, n: usize) -> bool {
+        if n > self.tailroom(buffer) {
+            return false;
+        }
+        let headroom = self.headroom(buffer);
+        let length = self.get_length(buffer) as usize;
+        self.set_data(buffer, headroom, length + n);
+        true
+    }
+
+    /// Removes `n` bytes from the end of the packet data of `buffer`.
+    /// Returns `false` and leaves the buffer unchanged if the packet is shorter than `n` bytes.
+    #[ensures(result == (n <= old(self.get_length(buffer)) as usize))]
+    #[ensures(self.headroom(buffer) == old(self.headroom(buffer)))]
+    #[ensures(result ==> self.get_length(buffer) as usize == old(self.get_length(buffer)) as usize - n)]
+    #[ensures(!result ==> self.get_length(buffer) == old(self.get_length(buffer)))]
+    #[ensures(self.refcount(buffer) == old(self.refcount(buffer)))]
+    pub fn trim(&mut self, buffer: &PacketBuffer, n: usize) -> bool {
+        let length = self.get_length(buffer) as usize;
+        if n > length {
+            return false;
+        }
+        let headroom = self.headroom(buffer);
+        self.set_data(buffer, headroom, length - n);
+        true
+    }
+
+    /// Places the packet data of `buffer` at `data_offset` bytes into its frame, with `length` bytes.
     #[inline(always)]
     #[trusted]
-    pub fn get_pkt_len(&self) -> u64{
-        (self.header_buffer_address.read() & 0xFFFF) >>  32//.get_bits(32..47) 
+    #[requires(data_offset + length <= ETHERNET_FRAME_SIZE)]
+    #[ensures(self.headroom(buffer) == data_offset)]
+    #[ensures(self.get_length(buffer) as usize == length)]
+    #[ensures(self.refcount(buffer) == old(self.refcount(buffer)))]
+    #[ensures(self.free_count() == old(self.free_count()))]
+    fn set_data(&mut self, buffer: &PacketBuffer, data_offset: usize, length: usize) {
+        let metadata = &mut self.buffers[buffer.0];
+        metadata.data_offset = data_offset as u16;
+        metadata.length = length as u16;
     }
- 
-}
 
-pub struct Mempool {
-    pub buffer_indices: VecWrapper<PacketBuffer>,
-    buffers: Vec<BufferMetadata>,
-}
+    /// Moves `n` buffers from the pool to the end of `out` if the pool has at least `n` free buffers,
+    /// otherwise leaves both untouched. Returns whether the buffers were allocated.
+    #[requires(out.len() + n <= VEC_CAPACITY)]
+    #[ensures(result == (old(self.free_count()) >= n))]
+    #[ensures(result ==> self.free_count() == old(self.free_count()) - n && out.len() == old(out.len()) + n)]
+    #[ensures(!result ==> self.free_count() == old(self.free_count()) && out.len() == old(out.len()))]
+    pub fn alloc_bulk(&mut self, n: usize, out: &mut VecWrapper<PacketBuffer>) -> bool {
+        if self.buffer_indices.len() < n {
+            return false;
+        }
+
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let free_count = self.buffer_indices.len();
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let out_len = out.len();
+        let mut i = 0;
+        while i < n {
+            body_invariant!(i < n);
+            body_invariant!(self.buffer_indices.len() == free_count - i);
+            body_invariant!(out.len() == out_len + i);
+            body_invariant!(out_len + n <= VEC_CAPACITY);
+
+            // the pool had at least `n` free buffers, so `pop` never returns `None` here
+            if let Some(buffer) = self.pop() {
+                out.push(buffer);
+            }
+            i += 1;
+        }
+        true
+    }
 
-impl Mempool {
-    pub fn pop(&mut self) -> Option<PacketBuffer> {
-        self.buffer_indices.pop()
+    /// Returns all buffers in `buffers` to the pool. Each of them has to be the last reference to its buffer.
+    /// Their metadata is reset when they are allocated again.
+    #[requires(forall(|i: usize| i < buffers.len() ==> self.refcount(buffers.index(i)) == 1))]
+    #[requires(self.free_count() + buffers.len() <= self.num_buffers())]
+    #[ensures(buffers.len() == 0)]
+    #[ensures(self.free_count() == old(self.free_count()) + old(buffers.len()))]
+    pub fn free_bulk(&mut self, buffers: &mut VecWrapper<PacketBuffer>) {
+        self.free_reserved(buffers)
+    }
+
+    /// Returns buffers that were taken with [`Mempool::alloc_bulk`] and never handed out, so they are the last references
+    /// to their buffers even though the specs can't show that their reference counts are still 1, see [`Mempool::refcount`].
+    #[requires(self.free_count() + buffers.len() <= self.num_buffers())]
+    #[ensures(buffers.len() == 0)]
+    #[ensures(self.free_count() == old(self.free_count()) + old(buffers.len()))]
+    pub(crate) fn free_reserved(&mut self, buffers: &mut VecWrapper<PacketBuffer>) {
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let free_count = self.buffer_indices.len();
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let len = buffers.len();
+        while let Some(buffer) = buffers.pop() {
+            body_invariant!(self.buffer_indices.len() + buffers.len() == free_count + len - 1);
+            self.push_free(buffer);
+        }
     }
 
     #[inline(always)]
     #[trusted]
+    #[ensures(buffer.index() < self.num_buffers() ==> result.value() == self.slot_paddr(buffer.index()))]
     pub fn phys_addr(&self, buffer: &PacketBuffer) -> PhysicalAddress {
-        self.buffers[buffer.0].paddr
+        self.mp.translate(self.offset(buffer))
+    }
+
+    /// The physical address of the packet data of `buffer`, which is where the NIC reads from or writes to.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(buffer.index() < self.num_buffers() ==> result.value() == self.slot_paddr(buffer.index()) + self.headroom(buffer))]
+    pub fn data_paddr(&self, buffer: &PacketBuffer) -> PhysicalAddress {
+        self.mp.translate(self.offset(buffer) + self.headroom(buffer))
     }
 
+    /// Returns the physical address and length of the packet data of `buffer`.
     #[inline(always)]
     #[trusted]
+    #[ensures(buffer.index() < self.num_buffers() ==> result.0.value() == self.slot_paddr(buffer.index()) + self.headroom(buffer))]
+    #[ensures(result.1 == self.get_length(buffer))]
     pub fn buffer_metadata(&self, buffer: &PacketBuffer) -> (PhysicalAddress, u16) {
-        (self.buffers[buffer.0].paddr, self.buffers[buffer.0].length)
+        (self.data_paddr(buffer), self.buffers[buffer.0].length)
     }
 
+    /// Sets the length of the packet data in `buffer`. A length that would go past the end of the frame
+    /// (which the NIC should never report) is cut off at the end of the frame.
     #[inline(always)]
     #[trusted]
+    #[ensures(self.headroom(buffer) == old(self.headroom(buffer)))]
+    #[ensures(self.headroom(buffer) + self.get_length(buffer) as usize <= ETHERNET_FRAME_SIZE)]
+    #[ensures(old(self.headroom(buffer)) + length as usize <= ETHERNET_FRAME_SIZE ==> self.get_length(buffer) == length)]
+    #[ensures(self.refcount(buffer) == old(self.refcount(buffer)))]
     pub fn set_length(&mut self, buffer: &PacketBuffer, length: u16) {
-        self.buffers[buffer.0].length = length;
+        let metadata = &mut self.buffers[buffer.0];
+        let max_length = ETHERNET_FRAME_SIZE - metadata.data_offset as usize;
+        metadata.length = core::cmp::min(length as usize, max_length) as u16;
     }
 
     #[inline(always)]
+    #[pure]
     #[trusted]
-    pub fn get_length(&mut self, buffer: &PacketBuffer) -> u16 {
+    pub fn get_length(&self, buffer: &PacketBuffer) -> u16 {
         self.buffers[buffer.0].length
     }
+
+    /// Returns the packet data stored in `buffer`: the `length` bytes after its headroom.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.len() == self.get_length(buffer) as usize)]
+    #[ensures(self.headroom(buffer) + result.len() <= ETHERNET_FRAME_SIZE)]
+    pub fn packet_data(&self, buffer: &PacketBuffer) -> &[u8] {
+        let metadata = &self.buffers[buffer.0];
+        let start = metadata.data_offset as usize;
+        &self.frame(buffer).as_bytes()[start..start + metadata.length as usize]
+    }
+
+    /// Returns the packet data stored in `buffer` for modification, with the same bounds as [`Mempool::packet_data`].
+    /// To grow or shrink the packet, call [`Mempool::prepend`], [`Mempool::append`] or [`Mempool::trim`] first.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.len() == old(self.get_length(buffer)) as usize)]
+    #[ensures(old(self.headroom(buffer)) + result.len() <= ETHERNET_FRAME_SIZE)]
+    #[after_expiry(self.get_length(buffer) == old(self.get_length(buffer)) && self.headroom(buffer) == old(self.headroom(buffer)))]
+    pub fn packet_data_mut(&mut self, buffer: &PacketBuffer) -> &mut [u8] {
+        let start = self.buffers[buffer.0].data_offset as usize;
+        let end = start + self.buffers[buffer.0].length as usize;
+        &mut self.frame_mut(buffer).as_bytes_mut()[start..end]
+    }
+
+    /// Returns the Ethernet header at the start of the packet data of `buffer`,
+    /// or `None` if the packet is too short to hold one.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.is_some() == (self.get_length(buffer) as usize >= ETHERNET_HEADER_SIZE))]
+    pub fn header(&self, buffer: &PacketBuffer) -> Option<&EthernetHeader> {
+        let data = self.packet_data(buffer);
+        if data.len() < ETHERNET_HEADER_SIZE {
+            return None;
+        }
+        // SAFETY: `EthernetHeader` is `repr(C)`, `ETHERNET_HEADER_SIZE` bytes long and has an alignment of 1,
+        // and the packet data is at least that long
+        Some(unsafe { &*(data.as_ptr() as *const EthernetHeader) })
+    }
+
+    /// Returns the Ethernet header at the start of the packet data of `buffer` for modification,
+    /// or `None` if the packet is too short to hold one.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.is_some() == (old(self.get_length(buffer)) as usize >= ETHERNET_HEADER_SIZE))]
+    #[after_expiry(self.get_length(buffer) == old(self.get_length(buffer)) && self.headroom(buffer) == old(self.headroom(buffer)))]
+    pub fn header_mut(&mut self, buffer: &PacketBuffer) -> Option<&mut EthernetHeader> {
+        let data = self.packet_data_mut(buffer);
+        if data.len() < ETHERNET_HEADER_SIZE {
+            return None;
+        }
+        // SAFETY: see `header()`, and the packet data is exclusively borrowed from the pool
+        Some(unsafe { &mut *(data.as_mut_ptr() as *mut EthernetHeader) })
+    }
+
+    /// Returns the whole frame of `buffer`, including the headroom in front of the packet data.
+    /// Its header fields are only those of the packet if the buffer has no headroom.
+    #[inline(always)]
+    #[trusted]
+    pub fn frame(&self, buffer: &PacketBuffer) -> &EthernetFrame {
+        let vaddr = self.mp.start_address() + self.offset(buffer);
+        // SAFETY: `offset()` checked that the frame lies inside `mp`, which is mapped for as long as the pool lives
+        unsafe { &*(vaddr as *const EthernetFrame) }
+    }
+
+    /// Returns the whole frame of `buffer` for modification, including the headroom in front of the packet data.
+    #[inline(always)]
+    #[trusted]
+    #[after_expiry(self.get_length(buffer) == old(self.get_length(buffer)) && self.headroom(buffer) == old(self.headroom(buffer)))]
+    pub fn frame_mut(&mut self, buffer: &PacketBuffer) -> &mut EthernetFrame {
+        let vaddr = self.mp.start_address() + self.offset(buffer);
+        // SAFETY: `offset()` checked that the frame lies inside `mp`, which is mapped for as long as the pool lives,
+        // and the frame can only be reached through the exclusive borrow of the pool
+        unsafe { &mut *(vaddr as *mut EthernetFrame) }
+    }
+
+    /// The byte offset of the frame of `buffer` in `mp`. Panics if `buffer` doesn't belong to this pool.
+    #[inline(always)]
+    #[trusted]
+    fn offset(&self, buffer: &PacketBuffer) -> usize {
+        assert!(buffer.0 < self.buffers.len());
+        buffer.0 * ETHERNET_FRAME_SIZE
+    }
+}
+
+#[trusted]
+fn new_buffer_metadata(num_buffers: usize) -> MetadataTable {
+    (0..num_buffers).map(|_| BufferMetadata { length: 0, data_offset: 0, refcount: 0 }).collect()
 }
 
+/// The metadata of the buffers of a `Mempool`, indexed by buffer.
+/// Without an allocator a pool can't have more buffers than a `VecWrapper` can hold anyway.
+#[cfg(feature = "alloc")]
+type MetadataTable = Vec<BufferMetadata>;
+#[cfg(not(feature = "alloc"))]
+type MetadataTable = FixedVec<BufferMetadata, FIXED_CAPACITY>;
+
 struct BufferMetadata {
-    frame: EthernetFrame,
-    paddr: PhysicalAddress,
-    length: u16
+    /// The number of bytes of packet data
+    length: u16,
+    /// The offset of the packet data in the frame, so that `data_offset + length <= ETHERNET_FRAME_SIZE`
+    data_offset: u16,
+    /// The number of `PacketBuffer` handles to this buffer outside of the free list
+    refcount: u16,
+}
+
+/// The size of the buffer that holds one Ethernet frame.
+pub const ETHERNET_FRAME_SIZE: usize = 2048;
+/// The number of bytes in a MAC address.
+pub const MAC_ADDR_LEN: usize = 6;
+/// The size of the header at the start of every Ethernet frame.
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+/// The largest headroom of a `Mempool`, which leaves 1 KiB of every buffer for received data,
+/// the smallest receive buffer size the NIC supports.
+pub const MAX_HEADROOM: usize = ETHERNET_FRAME_SIZE - 1024;
+
+register_layout! {
+    /// The header at the start of every Ethernet frame.
+    /// It only consists of byte arrays, so it can be placed at any offset of a buffer.
+    pub struct EthernetHeader, base 0x0, size 14 {
+        0x0 => pub dest_addr:  [u8; 6],
+        0x6 => pub src_addr:   [u8; 6],
+        /// The EtherType or length of the frame, in network byte order
+        0xC => pub ether_type: [u8; 2],
+    }
+}
+
+#[cfg(not(prusti))]
+const _: () = assert!(core::mem::align_of::<EthernetHeader>() == 1, "EthernetHeader must not require alignment");
+
+impl EthernetHeader {
+    #[inline(always)]
+    pub fn dest_addr(&self) -> [u8; MAC_ADDR_LEN] {
+        self.dest_addr
+    }
+
+    #[inline(always)]
+    #[ensures(self.dest_addr == addr)]
+    pub fn set_dest_addr(&mut self, addr: [u8; MAC_ADDR_LEN]) {
+        self.dest_addr = addr;
+    }
+
+    #[inline(always)]
+    pub fn src_addr(&self) -> [u8; MAC_ADDR_LEN] {
+        self.src_addr
+    }
+
+    #[inline(always)]
+    #[ensures(self.src_addr == addr)]
+    pub fn set_src_addr(&mut self, addr: [u8; MAC_ADDR_LEN]) {
+        self.src_addr = addr;
+    }
+
+    /// Returns the EtherType (or length) field in host byte order.
+    #[inline(always)]
+    #[trusted]
+    pub fn ether_type(&self) -> u16 {
+        u16::from_be_bytes(self.ether_type)
+    }
+
+    /// Sets the EtherType (or length) field, given in host byte order.
+    #[inline(always)]
+    #[trusted]
+    pub fn set_ether_type(&mut self, ether_type: u16) {
+        self.ether_type = ether_type.to_be_bytes();
+    }
 }
 
-pub struct EthernetFrame {
-    pub dest_addr:  [u8; 6],
-    pub src_addr:   [u8; 6],
-    pub length:     u16,
-    pub payload:    [u8; 1500],
-    _padding: [u8; 2048 - 1514]
+register_layout! {
+    /// The layout of a buffer without headroom.
+    pub struct EthernetFrame, base 0x0, size 2048 {
+        0x0 => pub header:     EthernetHeader,
+        0xE => pub payload:    [u8; 1500],
+        0x5EA => _padding: [u8; 2048 - 1514],
+    }
+}
+
+impl EthernetFrame {
+    /// Views the whole frame as bytes, in the order they are sent on the wire.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.len() == ETHERNET_FRAME_SIZE)]
+    pub fn as_bytes(&self) -> &[u8; ETHERNET_FRAME_SIZE] {
+        // SAFETY: `EthernetFrame` is `repr(C)`, exactly `ETHERNET_FRAME_SIZE` bytes long and has no padding bytes
+        unsafe { &*(self as *const EthernetFrame as *const [u8; ETHERNET_FRAME_SIZE]) }
+    }
+
+    /// Views the whole frame as mutable bytes, in the order they are sent on the wire.
+    #[inline(always)]
+    #[trusted]
+    #[ensures(result.len() == ETHERNET_FRAME_SIZE)]
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; ETHERNET_FRAME_SIZE] {
+        // SAFETY: `EthernetFrame` is `repr(C)`, exactly `ETHERNET_FRAME_SIZE` bytes long, has no padding bytes
+        // and every bit pattern is a valid `EthernetFrame`
+        unsafe { &mut *(self as *mut EthernetFrame as *mut [u8; ETHERNET_FRAME_SIZE]) }
+    }
 }
 
 pub struct PacketBuffer(usize);
 
+impl PacketBuffer {
+    /// The index of the buffer in its `Mempool`.
+    #[pure]
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
 impl core::cmp::PartialEq for PacketBuffer {
     #[pure]
     fn eq(&self, other: &Self) -> bool {
@@ -152,6 +742,16 @@ impl core::cmp::PartialEq for PacketBuffer {
     }
 }
 
+/// A buffer is identified by its index in the pool.
+impl Snapshot for PacketBuffer {
+    type Model = usize;
+
+    #[pure]
+    fn snapshot(&self) -> usize {
+        self.0
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct PhysicalAddress(usize);
 impl PhysicalAddress {
@@ -159,6 +759,12 @@ impl PhysicalAddress {
     pub fn value(&self) -> usize {
         self.0
     }
+
+    #[pure]
+    #[ensures(result.value() == value)]
+    pub fn new(value: usize) -> PhysicalAddress {
+        PhysicalAddress(value)
+    }
 }
 
 impl core::cmp::PartialEq for PhysicalAddress {
@@ -168,15 +774,125 @@ impl core::cmp::PartialEq for PhysicalAddress {
     }
 }
 
+impl Snapshot for PhysicalAddress {
+    type Model = usize;
+
+    #[pure]
+    fn snapshot(&self) -> usize {
+        self.0
+    }
+}
+
+/// The size of a page and of a frame, in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// A physical memory frame, identified by its number.
 pub struct Frame(usize);
 
+impl Frame {
+    pub fn new(number: usize) -> Frame {
+        Frame(number)
+    }
+
+    #[pure]
+    pub fn number(&self) -> usize {
+        self.0
+    }
+
+    #[pure]
+    #[requires(self.number() <= usize::MAX / PAGE_SIZE)]
+    pub fn start_address(&self) -> PhysicalAddress {
+        PhysicalAddress(self.0 * PAGE_SIZE)
+    }
+}
+
+/// A range of virtual pages, identified by the numbers of the first and last page.
 pub struct AllocatedPages(RangeInclusive<usize>);
 
+impl AllocatedPages {
+    #[requires(start <= end)]
+    pub fn new(start: usize, end: usize) -> AllocatedPages {
+        AllocatedPages(RangeInclusive { start, end })
+    }
+
+    #[pure]
+    pub fn start(&self) -> usize {
+        self.0.start
+    }
+
+    #[pure]
+    pub fn end(&self) -> usize {
+        self.0.end
+    }
+
+    #[pure]
+    #[trusted]
+    pub fn size_in_pages(&self) -> usize {
+        self.0.end + 1 - self.0.start
+    }
+}
+
 pub struct EntryFlags(u64);
 
+impl EntryFlags {
+    pub fn new(bits: u64) -> EntryFlags {
+        EntryFlags(bits)
+    }
+
+    #[pure]
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A range of virtual pages mapped to physically contiguous frames, starting at `frame`.
 pub struct MappedPages {
     page_table_p4: Frame,
     pages: AllocatedPages,
+    /// The frame that the first page is mapped to
+    frame: Frame,
+    flags: EntryFlags,
+}
+
+impl MappedPages {
+    /// # Safety
+    /// `pages` must be mapped in `page_table_p4`, with `flags`, to the contiguous frames starting at `frame`,
+    /// and the mapping must stay valid for the lifetime of the returned `MappedPages`.
+    #[requires(pages.size_in_pages() <= usize::MAX / PAGE_SIZE)]
+    #[requires(pages.start() <= usize::MAX / PAGE_SIZE - pages.size_in_pages())]
+    #[requires(frame.number() <= usize::MAX / PAGE_SIZE - pages.size_in_pages())]
+    pub unsafe fn new(page_table_p4: Frame, pages: AllocatedPages, frame: Frame, flags: EntryFlags) -> MappedPages {
+        MappedPages { page_table_p4, pages, frame, flags }
+    }
+
+    /// The frame of the top-level page table that the pages are mapped in.
+    pub fn page_table_p4(&self) -> &Frame {
+        &self.page_table_p4
+    }
+
+    pub fn flags(&self) -> &EntryFlags {
+        &self.flags
+    }
+}
+
+impl DmaMemory for MappedPages {
+    #[pure]
+    #[trusted]
+    fn start_address(&self) -> usize {
+        self.pages.start() * PAGE_SIZE
+    }
+
+    #[pure]
+    #[trusted]
+    fn start_paddr(&self) -> PhysicalAddress {
+        self.frame.start_address()
+    }
+
+    #[pure]
+    #[trusted]
+    fn size_in_bytes(&self) -> usize {
+        self.pages.size_in_pages() * PAGE_SIZE
+    }
 }
 
 
@@ -188,6 +904,26 @@ pub struct RxQueueRegisters {
     pub(crate) regs: Fragment<RegistersRx>
 }
 
+impl RxQueueRegisters {
+    /// Takes the registers of the receive queue `id`.
+    #[ensures(result.id() == id)]
+    pub fn new(id: usize, regs: Fragment<RegistersRx>) -> RxQueueRegisters {
+        RxQueueRegisters { id, regs }
+    }
+
+    #[pure]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The value that was last written to the Receive Descriptor Tail register. Only exists when compiling with Prusti.
+    #[cfg(prusti)]
+    #[pure]
+    pub fn rdt_model(&self) -> u32 {
+        self.regs.ptr.rdt.model()
+    }
+}
+
 impl Deref for RxQueueRegisters {
     type Target = Fragment<RegistersRx>;
 
@@ -202,51 +938,67 @@ impl DerefMut for RxQueueRegisters {
     }
 }
 
-pub struct Fragment<T> {
-    pub(crate) ptr: Box<T>,
+/// The registers of a queue, which live in the mapped register space of the NIC.
+/// They are borrowed for the lifetime of the driver, so dropping a `Fragment` never frees the mapping.
+pub struct Fragment<T: 'static> {
+    pub(crate) ptr: &'static mut T,
+}
+
+impl<T> Fragment<T> {
+    /// Wraps the registers at `ptr`, e.g. the register block of one queue in the mapped BAR of the NIC.
+    ///
+    /// # Safety
+    /// `ptr` has to point to a `T` which is mapped, aligned and valid for the rest of the program,
+    /// and nothing else may access it while the `Fragment` exists.
+    #[trusted]
+    pub unsafe fn new(ptr: *mut T) -> Fragment<T> {
+        Fragment { ptr: &mut *ptr }
+    }
 }
 
 impl<T> Deref for Fragment<T> {
-    type Target = Box<T>;
+    type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.ptr
+        Deref::deref(&self.ptr)
     }
 }
 
 impl<T> DerefMut for Fragment<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.ptr
+        DerefMut::deref_mut(&mut self.ptr)
     }
 }
 
-pub struct RegistersRx {
-    /// Receive Descriptor Base Address Low
-    pub rdbal:                          Volatile<u32>,        // 0x1000
+register_layout! {
+    pub struct RegistersRx, base 0x1000, size 64 {
+        /// Receive Descriptor Base Address Low
+        0x1000 => pub rdbal:                Volatile<u32>,
 
-    /// Recive Descriptor Base Address High
-    pub rdbah:                          Volatile<u32>,        // 0x1004
+        /// Recive Descriptor Base Address High
+        0x1004 => pub rdbah:                Volatile<u32>,
 
-    /// Recive Descriptor Length
-    pub rdlen:                          Volatile<u32>,        // 0x1008
+        /// Recive Descriptor Length
+        0x1008 => pub rdlen:                Volatile<u32>,
 
-    /// Rx DCA Control Register
-    dca_rxctrl:                         Volatile<u32>,          // 0x100C
+        /// Rx DCA Control Register
+        0x100C => dca_rxctrl:               Volatile<u32>,
 
-    /// Recive Descriptor Head
-    pub rdh:                            Volatile<u32>,          // 0x1010
+        /// Recive Descriptor Head, advanced by the NIC. It may only be written while the queue is disabled.
+        0x1010 => pub rdh:                  Volatile<u32>,
 
-    /// Split Receive Control Registers
-    srrctl:                             Volatile<u32>,          // 0x1014 //specify descriptor type
+        /// Split Receive Control Registers
+        0x1014 => srrctl:                   Volatile<u32>, //specify descriptor type
 
-    /// Receive Descriptor Tail
-    pub rdt:                            Volatile<u32>,          // 0x1018
-    _padding1:                          [u8;12],                // 0x101C - 0x1027
+        /// Receive Descriptor Tail
+        0x1018 => pub rdt:                  Volatile<u32>,
+        0x101C => _padding1:                [u8;12],
 
-    /// Receive Descriptor Control
-    rxdctl:                             Volatile<u32>,          // 0x1028
-    _padding2:                          [u8;20],                // 0x102C - 0x103F                                            
-} // 64B
+        /// Receive Descriptor Control
+        0x1028 => rxdctl:                   Volatile<u32>,
+        0x102C => _padding2:                [u8;20],
+    }
+}
 
 pub struct RangeInclusive<Idx: Clone + PartialOrd> {
     start: Idx,
@@ -255,23 +1007,35 @@ pub struct RangeInclusive<Idx: Clone + PartialOrd> {
 
 
 
-pub struct AdvancedTxDescriptor {
-    /// Starting physical address of the receive buffer for the packet.
-    pub packet_buffer_address:  Volatile<u64>,
-    /// Length of data buffer
-    pub data_len: Volatile<u16>,
-    /// A multi-part field:
-    /// * `dtyp`: Descriptor Type, occupies bits `[7:4]`,
-    /// * `mac`: options to apply LinkSec and time stamp, occupies bits `[3:2]`.
-    pub dtyp_mac_rsv : Volatile<u8>,
-    /// Command bits
-    pub dcmd:  Volatile<u8>,
-    /// A multi-part field:
-    /// * `paylen`: the size in bytes of the data buffer in host memory.
-    ///   not including the fields that the hardware adds), occupies bits `[31:14]`.
-    /// * `popts`: options to offload checksum calculation, occupies bits `[13:8]`.
-    /// * `sta`: status of the descriptor (whether it's in use or not), occupies bits `[3:0]`.
-    pub paylen_popts_cc_idx_sta: Volatile<u32>,
+register_layout! {
+    pub struct AdvancedTxDescriptor, base 0x0, size 16 {
+        /// Starting physical address of the receive buffer for the packet.
+        0x0 => pub packet_buffer_address:  Volatile<u64>,
+        /// Length of data buffer
+        0x8 => pub data_len: Volatile<u16>,
+        /// A multi-part field:
+        /// * `dtyp`: Descriptor Type, occupies bits `[7:4]`,
+        /// * `mac`: options to apply LinkSec and time stamp, occupies bits `[3:2]`.
+        0xA => pub dtyp_mac_rsv : Volatile<u8>,
+        /// Command bits
+        0xB => pub dcmd:  Volatile<u8>,
+        /// A multi-part field:
+        /// * `paylen`: the size in bytes of the data buffer in host memory.
+        ///   not including the fields that the hardware adds), occupies bits `[31:14]`.
+        /// * `popts`: options to offload checksum calculation, occupies bits `[13:8]`.
+        /// * `sta`: status of the descriptor (whether it's in use or not), occupies bits `[3:0]`.
+        0xC => pub paylen_popts_cc_idx_sta: Volatile<u32>,
+    }
+}
+
+bitfields! {
+    impl AdvancedTxDescriptor {
+        /// Status of the descriptor, written back by the NIC
+        pub get_sta: paylen_popts_cc_idx_sta, u32, bits(TX_STA_START, TX_STA_WIDTH);
+
+        /// The payload length that was last written to the descriptor
+        pub get_paylen: paylen_popts_cc_idx_sta, u32, bits(TX_PAYLEN_SHIFT as u32, TX_PAYLEN_WIDTH);
+    }
 }
 
 /// Tx Command: End of Packet
@@ -299,28 +1063,51 @@ pub const TX_DTYP_ADV:                     u8 = 0x3 << 4;
 /// Since we have divided the upper 64 bits into 4 parts (u16,u8,u8,u32),
 /// the paylen is then located at bit 14 of the upper 32 bits of the descriptor.
 pub const TX_PAYLEN_SHIFT:                 u8 = 46 - 32; //(actual offset - offset of variable) 
+/// Tx Descriptor paylen width, occupies bits `[31:14]` of `paylen_popts_cc_idx_sta`
+pub const TX_PAYLEN_WIDTH:                 u32 = 18;
+/// Tx Descriptor status, occupies bits `[3:0]` of `paylen_popts_cc_idx_sta`
+pub const TX_STA_START:                    u32 = 0;
+pub const TX_STA_WIDTH:                    u32 = 4;
 
 
+/// The snapshot of a descriptor is made of the values software last wrote to its fields.
+#[cfg(prusti)]
+impl Snapshot for AdvancedTxDescriptor {
+    type Model = (u64, u16, u8, u8, u32);
+
+    #[pure]
+    fn snapshot(&self) -> (u64, u16, u8, u8, u32) {
+        (
+            self.packet_buffer_address.model(),
+            self.data_len.model(),
+            self.dtyp_mac_rsv.model(),
+            self.dcmd.model(),
+            self.paylen_popts_cc_idx_sta.model(),
+        )
+    }
+}
+
 impl AdvancedTxDescriptor {
     #[inline(always)]
-    #[trusted]
+    #[ensures(self.packet_buffer_address.model() == transmit_buffer_addr.value() as u64)]
+    #[ensures(self.data_len.model() == transmit_buffer_length)]
+    #[ensures(get_bits_u32(self.paylen_popts_cc_idx_sta.model(), TX_PAYLEN_SHIFT as u32, TX_PAYLEN_WIDTH) == transmit_buffer_length as u32)]
     pub(crate) fn send(&mut self, transmit_buffer_addr: PhysicalAddress, transmit_buffer_length: u16) {
         self.packet_buffer_address.write(transmit_buffer_addr.value() as u64);
         self.data_len.write(transmit_buffer_length);
         self.dtyp_mac_rsv.write(TX_DTYP_ADV);
-        self.paylen_popts_cc_idx_sta.write((transmit_buffer_length as u32) << TX_PAYLEN_SHIFT);
+        self.paylen_popts_cc_idx_sta.write(set_bits_u32(0, TX_PAYLEN_SHIFT as u32, TX_PAYLEN_WIDTH, transmit_buffer_length as u32));
         self.dcmd.write(TX_CMD_DEXT | TX_CMD_RS | TX_CMD_IFCS | TX_CMD_EOP);
     }
 
     #[inline(always)]
-    #[trusted]
     pub fn desc_done(&self) -> bool {
-        (self.paylen_popts_cc_idx_sta.read() as u8 & TX_STATUS_DD) == TX_STATUS_DD
+        (self.get_sta() & TX_STATUS_DD as u32) == TX_STATUS_DD as u32
     }
 
 }
 
-pub(crate) struct TxQueueRegisters {
+pub struct TxQueueRegisters {
     /// the ID of the tx queue that these registers control
     id: usize,
     /// We prevent the drop handler from dropping the `regs` because the backing memory is not in the heap,
@@ -328,6 +1115,26 @@ pub(crate) struct TxQueueRegisters {
     pub(crate)regs: Fragment<RegistersTx>
 }
 
+impl TxQueueRegisters {
+    /// Takes the registers of the transmit queue `id`.
+    #[ensures(result.id() == id)]
+    pub fn new(id: usize, regs: Fragment<RegistersTx>) -> TxQueueRegisters {
+        TxQueueRegisters { id, regs }
+    }
+
+    #[pure]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The value that was last written to the Transmit Descriptor Tail register. Only exists when compiling with Prusti.
+    #[cfg(prusti)]
+    #[pure]
+    pub fn tdt_model(&self) -> u32 {
+        self.regs.ptr.tdt.model()
+    }
+}
+
 impl Deref for TxQueueRegisters {
     type Target = Fragment<RegistersTx>;
 
@@ -342,34 +1149,223 @@ impl DerefMut for TxQueueRegisters {
     }
 }
 
-pub(crate) struct RegistersTx {
-    /// Transmit Descriptor Base Address Low
-    pub tdbal:                          Volatile<u32>,        // 0x6000
+register_layout! {
+    pub struct RegistersTx, base 0x6000, size 64 {
+        /// Transmit Descriptor Base Address Low
+        0x6000 => pub tdbal:                Volatile<u32>,
 
-    /// Transmit Descriptor Base Address High
-    pub tdbah:                          Volatile<u32>,        // 0x6004
-    
-    /// Transmit Descriptor Length    
-    pub tdlen:                          Volatile<u32>,        // 0x6008
+        /// Transmit Descriptor Base Address High
+        0x6004 => pub tdbah:                Volatile<u32>,
 
-    /// Tx DCA Control Register
-    dca_txctrl:                         Volatile<u32>,          // 0x600C
+        /// Transmit Descriptor Length
+        0x6008 => pub tdlen:                Volatile<u32>,
 
-    /// Transmit Descriptor Head
-    pub tdh:                            Volatile<u32>,          // 0x6010
-    _padding0:                          [u8; 4],                // 0x6014 - 0x6017
+        /// Tx DCA Control Register
+        0x600C => dca_txctrl:               Volatile<u32>,
 
-    /// Transmit Descriptor Tail
-    pub tdt:                            Volatile<u32>,          // 0x6018
-    _padding1:                          [u8; 12],               // 0x601C - 0x6027
+        /// Transmit Descriptor Head, advanced by the NIC. It may only be written while the queue is disabled.
+        0x6010 => pub tdh:                  Volatile<u32>,
+        0x6014 => _padding0:                [u8; 4],
 
-    /// Transmit Descriptor Control
-    pub txdctl:                             Volatile<u32>,          // 0x6028
-    _padding2:                          [u8; 12],               // 0x602C - 0x6037
+        /// Transmit Descriptor Tail
+        0x6018 => pub tdt:                  Volatile<u32>,
+        0x601C => _padding1:                [u8; 12],
+
+        /// Transmit Descriptor Control
+        0x6028 => pub txdctl:               Volatile<u32>,
+        0x602C => _padding2:                [u8; 12],
+
+        /// Transmit Descriptor Completion Write Back Address Low
+        0x6038 => tdwbal:                   Volatile<u32>,
+
+        /// Transmit Descriptor Completion Write Back Address High
+        0x603C => tdwbah:                   Volatile<u32>,
+    }
+}
 
-    /// Transmit Descriptor Completion Write Back Address Low
-    tdwbal:                             Volatile<u32>,          // 0x6038
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dma::{HeapDma, HeapDmaAllocator};
 
-    /// Transmit Descriptor Completion Write Back Address High
-    tdwbah:                             Volatile<u32>,          // 0x603C
-} // 64B
+    /// A pool of `num_buffers` buffers on the heap, for the tests of the modules that use one.
+    pub(crate) fn pool(num_buffers: usize, headroom: usize) -> Mempool<HeapDma> {
+        let (mp, _paddr) = HeapDmaAllocator.alloc_dma(num_buffers * ETHERNET_FRAME_SIZE, PAGE_SIZE).unwrap();
+        Mempool::with_headroom(mp, headroom).unwrap()
+    }
+
+    /// The registers of a receive queue on the heap, where a test can play the NIC.
+    pub(crate) fn rx_regs() -> RxQueueRegisters {
+        // SAFETY: all-zero registers are valid, and the leaked box lives for the rest of the test
+        let regs = unsafe { Fragment::new(alloc::boxed::Box::leak(alloc::boxed::Box::new(core::mem::zeroed::<RegistersRx>()))) };
+        RxQueueRegisters::new(0, regs)
+    }
+
+    /// The registers of a transmit queue on the heap, where a test can play the NIC.
+    pub(crate) fn tx_regs() -> TxQueueRegisters {
+        // SAFETY: see `rx_regs()`
+        let regs = unsafe { Fragment::new(alloc::boxed::Box::leak(alloc::boxed::Box::new(core::mem::zeroed::<RegistersTx>()))) };
+        TxQueueRegisters::new(0, regs)
+    }
+
+    #[test]
+    fn pop_and_push() {
+        let mut pool = pool(4, 0);
+        assert_eq!(pool.num_buffers(), 4);
+        assert_eq!(pool.free_count(), 4);
+
+        let buffers: [PacketBuffer; 4] = core::array::from_fn(|_| pool.pop().unwrap());
+        assert_eq!(pool.free_count(), 0);
+        assert!(pool.pop().is_none());
+        for b in &buffers {
+            assert_eq!(pool.refcount(b), 1);
+            assert_eq!(pool.get_length(b), 0);
+            assert_eq!(pool.phys_addr(b).value(), pool.slot_paddr(b.index()));
+        }
+        for (i, a) in buffers.iter().enumerate() {
+            assert!(buffers[i + 1..].iter().all(|b| b.index() != a.index()));
+        }
+
+        for b in buffers {
+            pool.push(b);
+        }
+        assert_eq!(pool.free_count(), 4);
+    }
+
+    #[test]
+    fn physical_addresses_follow_the_slots() {
+        let pool = pool(3, 0);
+        assert_eq!(pool.slot_paddr(0) % PAGE_SIZE, 0);
+        for i in 1..3 {
+            assert_eq!(pool.slot_paddr(i), pool.slot_paddr(0) + i * ETHERNET_FRAME_SIZE);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn buffer_of_a_larger_pool() {
+        let small = pool(1, 0);
+        let _ = small.frame(&PacketBuffer(1));
+    }
+
+    #[test]
+    fn frame_casts() {
+        let mut pool = pool(2, 16);
+        let b = pool.pop().unwrap();
+        assert!(pool.append(&b, ETHERNET_HEADER_SIZE - 1));
+        assert!(pool.header(&b).is_none());
+        assert!(pool.header_mut(&b).is_none());
+
+        assert!(pool.append(&b, 1));
+        let header = pool.header_mut(&b).unwrap();
+        header.set_dest_addr([1, 2, 3, 4, 5, 6]);
+        header.set_src_addr([7, 8, 9, 10, 11, 12]);
+        header.set_ether_type(0x0800);
+
+        let header = pool.header(&b).unwrap();
+        assert_eq!(header.dest_addr(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(header.src_addr(), [7, 8, 9, 10, 11, 12]);
+        assert_eq!(header.ether_type(), 0x0800);
+        assert_eq!(pool.packet_data(&b), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0x08, 0x00]);
+
+        // the header is written after the headroom of the frame, at the physical address the NIC is given
+        let bytes = pool.frame(&b).as_bytes();
+        assert_eq!(&bytes[16..16 + ETHERNET_HEADER_SIZE], pool.packet_data(&b));
+        assert!(bytes[..16].iter().all(|&byte| byte == 0));
+        let offset = pool.data_paddr(&b).value() - pool.phys_addr(&b).value();
+        assert_eq!(offset, 16);
+
+        pool.packet_data_mut(&b)[13] = 0xDD;
+        assert_eq!(pool.header(&b).unwrap().ether_type(), 0x08DD);
+
+        // writing one frame leaves the neighbouring frame alone
+        let c = pool.pop().unwrap();
+        pool.frame_mut(&c).as_bytes_mut().fill(0xFF);
+        assert_eq!(pool.header(&b).unwrap().dest_addr(), [1, 2, 3, 4, 5, 6]);
+        assert!(pool.frame(&b).as_bytes()[16 + ETHERNET_HEADER_SIZE..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn bulk() {
+        let mut pool = pool(3, 0);
+        let mut out = VecWrapper::new();
+        assert!(!pool.alloc_bulk(4, &mut out));
+        assert_eq!(out.len(), 0);
+        assert!(pool.alloc_bulk(3, &mut out));
+        assert_eq!((out.len(), pool.free_count()), (3, 0));
+        pool.free_bulk(&mut out);
+        assert_eq!((out.len(), pool.free_count()), (0, 3));
+    }
+
+    #[test]
+    fn refcount() {
+        let mut pool = pool(2, 0);
+        let a = pool.pop().unwrap();
+        let b = pool.clone_ref(&a).unwrap();
+        assert_eq!(b.index(), a.index());
+        assert_eq!(pool.refcount(&a), 2);
+
+        assert!(!pool.release(b));
+        assert_eq!(pool.refcount(&a), 1);
+        assert_eq!(pool.free_count(), 1);
+
+        let a = pool.release_ref(a).unwrap();
+        assert_eq!(pool.refcount(&a), 1);
+        assert!(pool.release(a));
+        assert_eq!(pool.free_count(), 2);
+    }
+
+    #[test]
+    fn clone_ref_at_max_refcount() {
+        let mut pool = pool(1, 0);
+        let a = pool.pop().unwrap();
+        pool.set_refcount(&a, u16::MAX);
+        assert!(pool.clone_ref(&a).is_none());
+        assert_eq!(pool.refcount(&a), u16::MAX);
+    }
+
+    #[test]
+    fn headroom() {
+        let mut pool = pool(1, 128);
+        let b = pool.pop().unwrap();
+        assert_eq!(pool.headroom(&b), 128);
+        assert_eq!(pool.tailroom(&b), ETHERNET_FRAME_SIZE - 128);
+        assert_eq!(pool.data_paddr(&b).value(), pool.slot_paddr(b.index()) + 128);
+        assert_eq!(pool.rx_buffer_size(), ETHERNET_FRAME_SIZE - 128);
+
+        assert!(pool.append(&b, 100));
+        assert!(pool.prepend(&b, 28));
+        assert_eq!((pool.headroom(&b), pool.get_length(&b)), (100, 128));
+        assert_eq!(pool.data_paddr(&b).value(), pool.slot_paddr(b.index()) + 100);
+
+        assert!(!pool.prepend(&b, 101));
+        assert!(!pool.append(&b, pool.tailroom(&b) + 1));
+        assert!(!pool.trim(&b, 129));
+        assert_eq!((pool.headroom(&b), pool.get_length(&b)), (100, 128));
+
+        assert!(pool.trim(&b, 28));
+        assert_eq!((pool.headroom(&b), pool.get_length(&b)), (100, 100));
+        assert!(pool.append(&b, pool.tailroom(&b)));
+        assert_eq!(pool.tailroom(&b), 0);
+    }
+
+    #[test]
+    fn set_length_is_cut_off_at_the_end_of_the_frame() {
+        let mut pool = pool(1, 64);
+        let b = pool.pop().unwrap();
+        pool.set_length(&b, u16::MAX);
+        assert_eq!(pool.get_length(&b) as usize, ETHERNET_FRAME_SIZE - 64);
+    }
+
+    #[test]
+    fn metadata_is_reset_on_pop() {
+        let mut pool = pool(1, 32);
+        let b = pool.pop().unwrap();
+        assert!(pool.prepend(&b, 32));
+        assert!(pool.append(&b, 10));
+        pool.push(b);
+
+        let b = pool.pop().unwrap();
+        assert_eq!((pool.headroom(&b), pool.get_length(&b), pool.refcount(&b)), (32, 0, 1));
+    }
+}
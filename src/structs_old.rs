@@ -1,5 +1,7 @@
 use prusti_contracts::*;
 use core::ops::{DerefMut, Deref};
+use alloc::boxed::Box;
+use verified_specs::snapshot::Snapshot;
 
 pub struct Volatile<T: Copy>{
     inner: T
@@ -105,6 +107,15 @@ impl core::cmp::PartialEq for PacketBufferS {
     }
 }
 
+impl Snapshot for PacketBufferS {
+    type Model = usize;
+
+    #[pure]
+    fn snapshot(&self) -> usize {
+        self.phys_addr.0
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct PhysicalAddress(usize);
 impl PhysicalAddress {
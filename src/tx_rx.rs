@@ -1,72 +1,433 @@
-use std::ops::IndexMut;
-
-use crate::vector_spec::*;
+use verified_specs::vector_spec::*;
 use crate::structs::*;
-use crate::option_spec::*;
-use crate::result_spec::*;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use verified_specs::{snapshot::*, seq_spec::*, option_spec::*, result_spec::*};
+use crate::dma::{DmaMemory, DescriptorRing};
+use crate::ring_buf::RingBuf;
+use crate::error::DriverError;
+use crate::step_counter::StepCounter;
 
 use prusti_contracts::*;
 
+/// The default maximum number of descriptors of a queue, which bounds the buffers it can have in use.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 512;
+
+/// Why [`rx_batch`] stopped receiving packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxStopReason {
+    /// The next descriptor in the ring has not been filled by the NIC yet.
+    RingEmpty,
+    /// The mempool had no buffer left to replace the next received buffer with,
+    /// so the packet was left in the ring.
+    PoolExhausted,
+    /// `batch_size` packets were received, or a whole lap of the ring if that is smaller.
+    BatchFull,
+}
+
+/// The result of a call to [`rx_batch`].
+#[derive(Clone, Copy, Debug)]
+pub struct RxBatchOutcome {
+    /// The number of packets that were received and appended to the output buffers.
+    pub received: u16,
+    pub stopped_reason: RxStopReason,
+    /// The number of filled descriptors that were left in the ring because the mempool was exhausted.
+    /// Only non-zero if `stopped_reason` is `PoolExhausted`.
+    pub unreplenished: u16,
+}
+
+impl RxBatchOutcome {
+    #[pure]
+    pub fn received(&self) -> u16 {
+        self.received
+    }
+
+    #[pure]
+    pub fn unreplenished(&self) -> u16 {
+        self.unreplenished
+    }
+
+    #[pure]
+    pub fn is_ring_empty(&self) -> bool {
+        matches!(self.stopped_reason, RxStopReason::RingEmpty)
+    }
+
+    #[pure]
+    pub fn is_pool_exhausted(&self) -> bool {
+        matches!(self.stopped_reason, RxStopReason::PoolExhausted)
+    }
+
+    #[pure]
+    pub fn is_batch_full(&self) -> bool {
+        matches!(self.stopped_reason, RxStopReason::BatchFull)
+    }
+
+    /// Returns the error to report if the batch was cut short by the mempool running out of buffers.
+    #[ensures(result.is_some() == self.is_pool_exhausted())]
+    #[ensures(result.is_some() ==> peek_option(&result) == DriverError::PoolExhausted)]
+    pub fn error(&self) -> Option<DriverError> {
+        match self.stopped_reason {
+            RxStopReason::PoolExhausted => Some(DriverError::PoolExhausted),
+            _ => None,
+        }
+    }
+}
+
+/// A receive queue which owns its descriptor ring, registers and the buffers currently given to the NIC.
+/// The type invariant captures the preconditions of [`rx_batch`] so that callers only have to supply the batch arguments.
+#[invariant(self.rx_descs.len() > 0)]
+#[invariant(self.num_rx_descs as usize == self.rx_descs.len())]
+#[invariant(self.rx_bufs_in_use.len() == self.rx_descs.len())]
+#[invariant(self.rx_cur < self.num_rx_descs)]
+pub struct RxQueue<M: DmaMemory = MappedPages, const N: usize = DEFAULT_QUEUE_CAPACITY> {
+    /// The number of the queue, stored here for our convenience.
+    pub(crate) id: u8,
+    /// Registers for this receive queue
+    pub(crate) regs: RxQueueRegisters,
+    /// Receive descriptors
+    pub(crate) rx_descs: DescriptorRing<AdvancedRxDescriptor, M>,
+    /// The number of receive descriptors in the descriptor ring
+    pub(crate) num_rx_descs: u16,
+    /// Current receive descriptor index
+    pub(crate) rx_cur: u16,
+    /// The list of rx buffers, in which the index in the vector corresponds to the index in `rx_descs`.
+    /// For example, `rx_descs[2]` is the receive descriptor corresponding to the receive buffer `rx_bufs_in_use[2]`.
+    pub(crate) rx_bufs_in_use: RingBuf<PacketBuffer, N>,
+    /// The work done by [`RxQueue::rx_batch`] since the queue was created or the counter was reset
+    pub(crate) steps: StepCounter,
+}
+
+impl<M: DmaMemory, const N: usize> RxQueue<M, N> {
+    /// Creates a queue which gives the NIC one buffer from `pool` per descriptor, `rx_bufs_in_use[i]` for `rx_descs[i]`,
+    /// and points every descriptor at the packet data of its buffer.
+    /// The registers are left to the caller, which writes the ring address, length, head and tail and enables the queue.
+    #[requires(rx_descs.len() > 0 && rx_descs.len() <= u16::MAX as usize)]
+    #[requires(rx_bufs_in_use.len() == rx_descs.len())]
+    #[ensures(result.rx_cur == 0)]
+    #[ensures(result.num_rx_descs as usize == result.rx_descs.len())]
+    #[ensures(result.steps().iterations() == 0 && result.steps().register_writes() == 0)]
+    pub fn new<P: DmaMemory>(
+        id: u8,
+        regs: RxQueueRegisters,
+        mut rx_descs: DescriptorRing<AdvancedRxDescriptor, M>,
+        rx_bufs_in_use: RingBuf<PacketBuffer, N>,
+        pool: &Mempool<P>
+    ) -> RxQueue<M, N> {
+        let num_rx_descs = rx_descs.len() as u16;
+        let descs = rx_descs.descs_mut();
+        let mut i = 0;
+        while i < descs.len() {
+            body_invariant!(i < descs.len());
+            body_invariant!(descs.len() == rx_bufs_in_use.len());
+            descs[i].set_packet_address(pool.data_paddr(rx_bufs_in_use.index(i)));
+            i += 1;
+        }
+        RxQueue { id, regs, rx_descs, num_rx_descs, rx_cur: 0, rx_bufs_in_use, steps: StepCounter::new() }
+    }
+
+    #[pure]
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    #[pure]
+    pub fn steps(&self) -> StepCounter {
+        self.steps
+    }
+
+    #[ensures(self.steps().iterations() == 0 && self.steps().register_writes() == 0)]
+    pub fn reset_steps(&mut self) {
+        self.steps.reset();
+    }
+
+    /// Receives up to `batch_size` packets, appends them to `buffers`
+    /// and replaces the used receive buffers with new ones from `pool`.
+    /// See [`rx_batch`] for the errors it returns.
+    #[requires(buffers.len() + batch_limit(batch_size, self.num_rx_descs) <= VEC_CAPACITY)]
+    #[ensures(result.is_err() ==> peek_err(&result) == DriverError::MultiDescriptorUnsupported)]
+    #[ensures(buffers.len() >= old(buffers.len()))]
+    #[ensures(pool.free_count() + buffers.len() == old(pool.free_count()) + old(buffers.len()))]
+    #[ensures(result.is_ok() ==> (peek_result(&result).received() != 0 ==> (old(self.rx_cur) as usize + peek_result(&result).received() as usize) % self.num_rx_descs as usize == self.rx_cur as usize))]
+    #[ensures(result.is_ok() ==> (peek_result(&result).received() == 0 ==> old(self.rx_cur) == self.rx_cur))]
+    #[ensures(result.is_ok() ==> buffers.len() == old(buffers.len()) + peek_result(&result).received() as usize)]
+    #[ensures(result.is_ok() ==> pool.free_count() == old(pool.free_count()) - peek_result(&result).received() as usize)]
+    #[ensures(result.is_ok() ==> peek_result(&result).is_batch_full() == (peek_result(&result).received() as usize == batch_limit(batch_size, self.num_rx_descs)))]
+    #[ensures(result.is_ok() ==> peek_result(&result).is_pool_exhausted() == (peek_result(&result).unreplenished() != 0))]
+    #[ensures(result.is_ok() ==> (peek_result(&result).is_pool_exhausted() ==> peek_result(&result).received() as usize == old(pool.free_count())))]
+    #[ensures(result.is_ok() ==> peek_result(&result).received() as usize + peek_result(&result).unreplenished() as usize <= batch_limit(batch_size, self.num_rx_descs))]
+    #[ensures(result.is_ok() ==> self.steps().iterations_within(old(self.steps().iterations()), batch_limit(batch_size, self.num_rx_descs)))]
+    #[ensures(result.is_err() ==> self.steps().iterations_within(old(self.steps().iterations()), batch_limit(batch_size, self.num_rx_descs) + 2 * self.num_rx_descs as usize))]
+    #[ensures(self.steps().register_writes_within(old(self.steps().register_writes()), 1))]
+    #[ensures(result.is_ok() ==> (peek_result(&result).received() != 0 ==> (self.regs.rdt_model() + 1) % self.num_rx_descs as u32 == self.rx_cur as u32))]
+    #[ensures(result.is_ok() ==> (peek_result(&result).received() == 0 ==> self.regs.rdt_model() == old(self.regs.rdt_model())))]
+    pub fn rx_batch<P: DmaMemory>(&mut self, buffers: &mut VecWrapper<PacketBuffer>, batch_size: usize, pool: &mut Mempool<P>) -> Result<RxBatchOutcome, DriverError> {
+        rx_batch(
+            self.rx_descs.descs_mut(), &mut self.rx_cur, &mut self.rx_bufs_in_use, &mut self.regs, self.num_rx_descs,
+            buffers, batch_size, pool, &mut self.steps
+        )
+    }
+}
+
+/// A transmit queue which owns its descriptor ring, registers and the buffers currently given to the NIC.
+/// The type invariant captures the preconditions of [`tx_batch`] so that callers only have to supply the batch arguments.
+#[invariant(self.tx_descs.len() > 0)]
+#[invariant(self.num_tx_descs as usize == self.tx_descs.len())]
+#[invariant(self.tx_cur < self.num_tx_descs)]
+#[invariant(self.tx_clean < self.num_tx_descs)]
+#[invariant(self.num_tx_descs as usize <= N)]
+#[invariant(self.max_clean > 0)]
+pub struct TxQueue<M: DmaMemory = MappedPages, const N: usize = DEFAULT_QUEUE_CAPACITY> {
+    /// The number of the queue, stored here for our convenience.
+    pub(crate) id: u8,
+    /// Registers for this transmit queue
+    pub(crate) regs: TxQueueRegisters,
+    /// Transmit descriptors
+    pub(crate) tx_descs: DescriptorRing<AdvancedTxDescriptor, M>,
+    /// The number of transmit descriptors in the descriptor ring
+    pub(crate) num_tx_descs: u16,
+    /// The first descriptor that has not yet been cleaned
+    pub(crate) tx_clean: u16,
+    /// Current transmit descriptor index
+    pub(crate) tx_cur: u16,
+    /// Buffers that have been handed to the NIC and have not yet been cleaned, in the order they were sent.
+    pub(crate) tx_bufs_in_use: RingBuf<PacketBuffer, N>,
+    /// The most buffers a single call cleans, which bounds its work
+    pub(crate) max_clean: u16,
+    /// The work done by [`TxQueue::tx_batch`] and [`TxQueue::tx_clean_into_pool`] since the queue was created or the counter was reset
+    pub(crate) steps: StepCounter,
+}
+
+impl<M: DmaMemory, const N: usize> TxQueue<M, N> {
+    /// Creates an empty queue on a zeroed descriptor ring.
+    /// The registers are left to the caller, which writes the ring address, length, head and tail and enables the queue.
+    #[requires(tx_descs.len() > 0 && tx_descs.len() <= u16::MAX as usize && tx_descs.len() <= N)]
+    #[ensures(result.tx_cur == 0 && result.tx_clean == 0)]
+    #[ensures(result.tx_bufs_in_use.len() == 0)]
+    #[ensures(result.num_tx_descs as usize == result.tx_descs.len())]
+    #[ensures(result.max_clean() == u16::MAX)]
+    #[ensures(result.steps().iterations() == 0 && result.steps().register_writes() == 0)]
+    pub fn new(id: u8, regs: TxQueueRegisters, tx_descs: DescriptorRing<AdvancedTxDescriptor, M>) -> TxQueue<M, N> {
+        let num_tx_descs = tx_descs.len() as u16;
+        TxQueue {
+            id, regs, tx_descs, num_tx_descs, tx_clean: 0, tx_cur: 0, tx_bufs_in_use: RingBuf::new(),
+            max_clean: u16::MAX, steps: StepCounter::new()
+        }
+    }
+
+    #[pure]
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    #[pure]
+    pub fn max_clean(&self) -> u16 {
+        self.max_clean
+    }
+
+    /// Sets the most buffers a call to [`TxQueue::tx_batch`] or [`TxQueue::tx_clean_into_pool`] cleans.
+    /// It starts out as `u16::MAX`, more than a ring has descriptors, so that every call cleans all it can.
+    /// It can't be 0, as the queue would then never clean any descriptor and fill up for good.
+    #[requires(max_clean > 0)]
+    #[ensures(self.max_clean() == max_clean)]
+    pub fn set_max_clean(&mut self, max_clean: u16) {
+        self.max_clean = max_clean;
+    }
+
+    #[pure]
+    pub fn steps(&self) -> StepCounter {
+        self.steps
+    }
+
+    #[ensures(self.steps().iterations() == 0 && self.steps().register_writes() == 0)]
+    pub fn reset_steps(&mut self) {
+        self.steps.reset();
+    }
+
+    /// Cleans completed descriptors, moving the buffers whose last reference was dropped into `used_buffers`,
+    /// then sends up to `batch_size` packets from `buffers`.
+    /// Returns the number of packets sent and the number of descriptors that were cleaned.
+    /// See [`tx_batch`] for the errors it returns.
+    #[requires(used_buffers.len() + self.num_tx_descs as usize <= VEC_CAPACITY)]
+    #[ensures(result.is_ok() ==> (old(self.tx_cur) as usize + peek_result(&result).0 as usize) % self.num_tx_descs as usize == self.tx_cur as usize)]
+    #[ensures(result.is_ok() ==> buffers.len() == old(buffers.len()) - peek_result(&result).0 as usize)]
+    #[ensures(used_buffers.len() >= old(used_buffers.len()))]
+    #[ensures(result.is_ok() ==> used_buffers.len() <= old(used_buffers.len()) + peek_result(&result).1)]
+    #[ensures((result.is_ok() && peek_result(&result).0 != 0) ==> self.regs.tdt_model() == self.tx_cur as u32)]
+    #[ensures((result.is_err() || peek_result(&result).0 == 0) ==> self.regs.tdt_model() == old(self.regs.tdt_model()))]
+    #[ensures(result.is_err() ==> peek_err(&result) == DriverError::InvalidRingState || peek_err(&result) == DriverError::QueueFull)]
+    #[ensures(result.is_err() ==> buffers.len() == old(buffers.len()) && self.tx_cur == old(self.tx_cur))]
+    #[ensures((result.is_err() && peek_err(&result) == DriverError::InvalidRingState) ==> used_buffers.len() == old(used_buffers.len()))]
+    #[ensures(result.is_ok() ==> peek_result(&result).1 <= self.max_clean() as usize)]
+    #[ensures(self.steps().iterations_within(old(self.steps().iterations()), self.max_clean() as usize + batch_limit(batch_size, self.num_tx_descs)))]
+    #[ensures(self.steps().register_writes_within(old(self.steps().register_writes()), 1))]
+    pub fn tx_batch<P: DmaMemory>(
+        &mut self,
+        batch_size: usize,
+        buffers: &mut VecWrapper<PacketBuffer>,
+        used_buffers: &mut VecWrapper<PacketBuffer>,
+        pool: &mut Mempool<P>
+    ) -> Result<(u16, usize), DriverError> {
+        tx_batch(
+            self.tx_descs.descs_mut(), &mut self.tx_bufs_in_use, self.num_tx_descs, &mut self.tx_clean, &mut self.tx_cur, 
+            &mut self.regs, batch_size, buffers, used_buffers, pool, self.max_clean, &mut self.steps
+        )
+    }
+
+    /// Cleans completed descriptors and frees their buffers straight back to `pool`.
+    /// Returns the number of descriptors that were cleaned and the number of buffers that were freed.
+    /// See [`tx_clean_into_pool`] for the errors it returns.
+    #[ensures(result.is_ok() ==> self.tx_bufs_in_use.len() == old(self.tx_bufs_in_use.len()) - peek_result(&result).0)]
+    #[ensures(result.is_ok() ==> pool.free_count() == old(pool.free_count()) + peek_result(&result).1)]
+    #[ensures(result.is_ok() ==> peek_result(&result).1 <= peek_result(&result).0)]
+    #[ensures(result.is_err() ==> peek_err(&result) == DriverError::InvalidRingState)]
+    #[ensures(result.is_err() ==> self.tx_bufs_in_use.len() == old(self.tx_bufs_in_use.len()) && pool.free_count() == old(pool.free_count()))]
+    #[ensures(result.is_ok() ==> peek_result(&result).0 <= self.max_clean() as usize)]
+    #[ensures(self.steps().iterations_within(old(self.steps().iterations()), self.max_clean() as usize))]
+    #[ensures(self.steps().register_writes() == old(self.steps().register_writes()))]
+    pub fn tx_clean_into_pool<P: DmaMemory>(&mut self, pool: &mut Mempool<P>) -> Result<(usize, usize), DriverError> {
+        tx_clean_into_pool(
+            self.tx_descs.descs(), &mut self.tx_bufs_in_use, &mut self.tx_clean, self.num_tx_descs, &self.regs,
+            pool, self.max_clean, &mut self.steps
+        )
+    }
+}
+
+/// The most packets a batch of `batch_size` can move through a ring of `num_descs` descriptors,
+/// since each descriptor is used at most once before the tail register is written.
+#[pure]
+pub fn batch_limit(batch_size: usize, num_descs: u16) -> usize {
+    if batch_size < num_descs as usize { batch_size } else { num_descs as usize }
+}
+
+/// Receives up to `batch_size` packets from the ring into `buffers`, replacing them with buffers from `pool`.
+/// The replacements are reserved with [`Mempool::alloc_bulk`] before the ring is read, and the batch stops with
+/// `PoolExhausted` once they are used up, counting the filled descriptors it had to leave in `unreplenished`.
+/// At most one lap of the ring is received per call, see [`batch_limit`].
+/// `steps` counts at most that many loop iterations and at most one register write.
+///
+/// Returns `MultiDescriptorUnsupported` when the NIC split a packet over several descriptors.
+/// The packets received before that one are still appended to `buffers` and given back to the NIC.
+/// The split packet is dropped: once the NIC has written back all of its descriptors, they are given back to the NIC
+/// with the buffers they already hold, so that the next call starts after it.
+/// Until then it is left in the ring, and dropping it takes at most two more laps of the ring worth of `steps`.
 #[requires(0 <= *rx_cur_stored && (*rx_cur_stored as usize) < rx_descs.len())]
 #[requires(0 <= *rx_cur_stored && (*rx_cur_stored as usize) < rx_bufs_in_use.len())]
 #[requires(rx_descs.len() > 0)]
 #[requires((num_rx_descs as usize) == rx_descs.len())]
 #[requires(rx_bufs_in_use.len() == rx_descs.len())]
-#[ensures((result.is_ok() && peek_result(&result) != 0) ==> (old(*rx_cur_stored) + peek_result(&result)) % num_rx_descs == *rx_cur_stored)]
-#[ensures((result.is_ok() && peek_result(&result) == 0) ==> old(*rx_cur_stored) == *rx_cur_stored)]
-#[ensures(result.is_ok()  ==> buffers.len() == old(buffers.len()) + peek_result(&result) as usize)]
-#[ensures(result.is_ok()  ==> rx_bufs_in_use.len() == old(rx_bufs_in_use.len()))]
-#[after_expiry(result.is_ok() ==> forall (|i: usize| 0<= i && i < peek_result(&result) as usize ==> {
-    let rx_cur = (old(*rx_cur_stored) + i as u16) % num_rx_descs;
-    let old_buffer_len = old(buffers.len());
-    buffers.index(old_buffer_len + i).phys_addr.value() == old(rx_bufs_in_use.index(rx_cur as usize)).phys_addr.value()
-}))]
-pub fn rx_batch(
+#[requires(buffers.len() + batch_limit(batch_size, num_rx_descs) <= VEC_CAPACITY)]
+#[ensures(result.is_err() ==> peek_err(&result) == DriverError::MultiDescriptorUnsupported)]
+#[ensures(buffers.len() >= old(buffers.len()))]
+#[ensures(pool.free_count() + buffers.len() == old(pool.free_count()) + old(buffers.len()))]
+#[ensures(result.is_ok() ==> (peek_result(&result).received() != 0 ==> (old(*rx_cur_stored) as usize + peek_result(&result).received() as usize) % num_rx_descs as usize == *rx_cur_stored as usize))]
+#[ensures(result.is_ok() ==> (peek_result(&result).received() == 0 ==> old(*rx_cur_stored) == *rx_cur_stored))]
+#[ensures(*rx_cur_stored < num_rx_descs)]
+#[ensures(result.is_ok() ==> buffers.len() == old(buffers.len()) + peek_result(&result).received() as usize)]
+#[ensures(rx_bufs_in_use.len() == old(rx_bufs_in_use.len()))]
+#[ensures(result.is_ok() ==> pool.free_count() == old(pool.free_count()) - peek_result(&result).received() as usize)]
+#[ensures(result.is_ok() ==> peek_result(&result).is_batch_full() == (peek_result(&result).received() as usize == batch_limit(batch_size, num_rx_descs)))]
+#[ensures(result.is_ok() ==> peek_result(&result).is_pool_exhausted() == (peek_result(&result).unreplenished() != 0))]
+#[ensures(result.is_ok() ==> (peek_result(&result).is_pool_exhausted() ==> peek_result(&result).received() as usize == old(pool.free_count())))]
+#[ensures(result.is_ok() ==> peek_result(&result).received() as usize + peek_result(&result).unreplenished() as usize <= batch_limit(batch_size, num_rx_descs))]
+#[ensures(result.is_ok() ==> steps.iterations_within(old(steps.iterations()), batch_limit(batch_size, num_rx_descs)))]
+#[ensures(result.is_err() ==> steps.iterations_within(old(steps.iterations()), batch_limit(batch_size, num_rx_descs) + 2 * num_rx_descs as usize))]
+#[ensures(steps.register_writes_within(old(steps.register_writes()), 1))]
+#[ensures(result.is_ok() ==> (peek_result(&result).received() != 0 ==> regs.rdt_model() < num_rx_descs as u32))]
+#[ensures(result.is_ok() ==> (peek_result(&result).received() != 0 ==> (regs.rdt_model() + 1) % num_rx_descs as u32 == *rx_cur_stored as u32))]
+#[ensures(result.is_ok() ==> (peek_result(&result).received() == 0 ==> regs.rdt_model() == old(regs.rdt_model())))]
+#[after_expiry(buffers.view() == old(buffers.view()).concat(
+    seq_ring_window(old(rx_bufs_in_use.view()), old(*rx_cur_stored) as usize, buffers.len() - old(buffers.len()), num_rx_descs as usize)
+))]
+pub fn rx_batch<P: DmaMemory, const N: usize>(
     rx_descs: &mut [AdvancedRxDescriptor], 
     rx_cur_stored: &mut u16, 
-    rx_bufs_in_use: &mut VecWrapper<PacketBuffer>,
+    rx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
     regs: &mut RxQueueRegisters,
     num_rx_descs: u16,
     buffers: &mut VecWrapper<PacketBuffer>, 
     batch_size: usize, 
-    pool: &mut Mempool
-) -> u16 {
+    pool: &mut Mempool<P>,
+    steps: &mut StepCounter
+) -> Result<RxBatchOutcome, DriverError> {
+    let limit = batch_limit(batch_size, num_rx_descs);
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let iterations = steps.iterations();
+
+    // Reserve the buffers to replenish the ring with up front, as many as the batch can use or the pool has.
+    // Once they are used up the batch stops with `PoolExhausted`, without asking the pool again,
+    // and the ones that are left over are given back to the pool at the end.
+    let free_count = pool.free_count();
+    let num_reserved = if limit < free_count { limit } else { free_count };
+    let mut reserved = VecWrapper::new();
+    pool.alloc_bulk(num_reserved, &mut reserved);
+
     let mut rx_cur = *rx_cur_stored;
     let mut last_rx_cur = *rx_cur_stored;
 
     // have to add this in for verification because the verifier can't reason that taking the remainder after each increment, 
-    // or taking the remainder after all increments is equivalent
-    let mut rx_cur_total = *rx_cur_stored; 
+    // or taking the remainder after all increments is equivalent.
+    // It can go past the end of the ring, so it is a `usize` to not overflow with a ring of `u16::MAX` descriptors.
+    let mut rx_cur_total = *rx_cur_stored as usize; 
     
     let mut rcvd_pkts = 0;
     let mut i = 0;
+    #[cfg_attr(not(prusti), allow(unused_variables))]
     let buffers_len = buffers.len();
+    let mut out_of_buffers = false;
+    let mut multi_desc = false;
+    let mut stopped_reason = RxStopReason::BatchFull;
 
-    while i < batch_size {
+    while i < limit {
+        body_invariant!(i < limit);
+        body_invariant!(steps.iterations_within(iterations, i));
+        body_invariant!(steps.register_writes() == old(steps.register_writes()));
+        body_invariant!(i <= num_reserved);
+        body_invariant!(reserved.len() == num_reserved - i);
+        body_invariant!(pool.free_count() == free_count - num_reserved);
+        body_invariant!(free_count <= pool.num_buffers());
+        body_invariant!(!out_of_buffers);
+        body_invariant!(!multi_desc);
         body_invariant!(num_rx_descs as usize == rx_descs.len());
         body_invariant!(rx_cur  < num_rx_descs);
         body_invariant!(rx_bufs_in_use.len() == rx_descs.len());
         body_invariant!(rcvd_pkts as usize == i);
         body_invariant!((rx_cur == last_rx_cur) || rx_cur == (last_rx_cur + 1) % num_rx_descs);
-        body_invariant!(*rx_cur_stored + rcvd_pkts == rx_cur_total);
+        body_invariant!(last_rx_cur < num_rx_descs);
+        body_invariant!(rcvd_pkts != 0 ==> (last_rx_cur + 1) % num_rx_descs == rx_cur);
+        body_invariant!(rx_cur as usize == rx_cur_total % num_rx_descs as usize);
+        body_invariant!(regs.rdt_model() == old(regs.rdt_model()));
+        body_invariant!(*rx_cur_stored as usize + rcvd_pkts as usize == rx_cur_total);
         // body_invariant!((rx_cur == rx_cur_total) || (rx_cur_total % num_rx_descs == rx_cur));
         body_invariant!(buffers.len() == buffers_len + rcvd_pkts as usize);
+        body_invariant!(buffers_len + limit <= VEC_CAPACITY);
+        body_invariant!(buffers.view() == old(buffers.view()).concat(
+            seq_ring_window(old(rx_bufs_in_use.view()), *rx_cur_stored as usize, rcvd_pkts as usize, num_rx_descs as usize)
+        ));
+        body_invariant!(seq_ring_window(rx_bufs_in_use.view(), rx_cur as usize, num_rx_descs as usize - i, num_rx_descs as usize)
+            == seq_ring_window(old(rx_bufs_in_use.view()), rx_cur as usize, num_rx_descs as usize - i, num_rx_descs as usize));
 
+        steps.count_iteration();
         let desc = index_mut(rx_descs, rx_cur as usize);
         let (dd, length) = desc.rx_metadata();
         if !dd {
+            stopped_reason = RxStopReason::RingEmpty;
+            break;
+        }
+        if !desc.end_of_packet() {
+            multi_desc = true;
             break;
         }
 
         // Now that we are "removing" the current receive buffer from the list of receive buffers that the NIC can use,
         // (because we're saving it for higher layers to use),
         // we need to obtain a new `ReceiveBuffer` and set it up such that the NIC will use it for future receivals.
-        if let Some(new_receive_buf) = pool.pop() {
+        let new_receive_buf = reserved.pop();
+        if let Some(new_receive_buf) = new_receive_buf {
             // actually tell the NIC about the new receive buffer, and that it's ready for use now
-            desc.set_packet_address(pool.phys_addr(&new_receive_buf));
+            desc.set_packet_address(pool.data_paddr(&new_receive_buf));
             
-            let mut current_rx_buf = replace(rx_bufs_in_use.index_mut(rx_cur as usize), new_receive_buf);
-            pool.set_length(&current_rx_buf, length as u16); // set the ReceiveBuffer's length to the size of the actual packet received
+            let current_rx_buf = replace(rx_bufs_in_use.index_mut(rx_cur as usize), new_receive_buf);
+            pool.set_length(&current_rx_buf, length); // set the ReceiveBuffer's length to the size of the actual packet received
             buffers.push(current_rx_buf);
 
             rcvd_pkts += 1;
@@ -75,20 +436,106 @@ pub fn rx_batch(
             rx_cur = (rx_cur + 1) % num_rx_descs;
             // prusti_assert!((last_rx_cur + rcvd_pkts) % rx_descs.len() == rx_cur)
         } else {
+            out_of_buffers = true;
+            stopped_reason = RxStopReason::PoolExhausted;
             break;
         }
         i += 1;
     }
+    pool.free_reserved(&mut reserved);
 
-    prusti_assert!(rx_cur_total == *rx_cur_stored + rcvd_pkts);
-    // if last_rx_cur != rx_cur {
-        // *rx_cur_stored = rx_cur as u16;
-    if rcvd_pkts != 0 {
-        *rx_cur_stored = rx_cur_total % num_rx_descs;
-        regs.rdt.write(last_rx_cur as u32); 
+    // Count the descriptors the NIC has already filled that we had to leave in the ring for lack of buffers,
+    // without looking further than the rest of the batch.
+    // The descriptor at `rx_cur` was already found filled by the iteration that ran out of buffers, so it is counted
+    // without reading it again, which keeps the whole call within `limit` iterations.
+    let mut unreplenished: u16 = 0;
+    if out_of_buffers {
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let scan_iterations = steps.iterations();
+        unreplenished = 1;
+        let mut cur = (rx_cur + 1) % num_rx_descs;
+        while (rcvd_pkts as usize + unreplenished as usize) < limit {
+            body_invariant!(cur < num_rx_descs);
+            body_invariant!(unreplenished >= 1);
+            body_invariant!((rcvd_pkts as usize + unreplenished as usize) < limit);
+            body_invariant!(scan_iterations >= iterations && scan_iterations - iterations <= rcvd_pkts as usize + 1);
+            body_invariant!(steps.iterations_within(scan_iterations, unreplenished as usize - 1));
+            body_invariant!(steps.register_writes() == old(steps.register_writes()));
+            steps.count_iteration();
+            if !index_mut(rx_descs, cur as usize).rx_metadata().0 {
+                break;
+            }
+            unreplenished += 1;
+            cur = (cur + 1) % num_rx_descs;
+        }
     }
 
-   rcvd_pkts
+    prusti_assert!(rx_cur_total == *rx_cur_stored as usize + rcvd_pkts as usize);
+
+    // Drop the packet that was split over several descriptors, so that the ring doesn't stay stuck on it.
+    // First find its last descriptor, without looking further than the rest of the lap,
+    // and only if the NIC has written back all of them give them back to the NIC with the buffers they already hold.
+    if multi_desc {
+        #[cfg_attr(not(prusti), allow(unused_variables))]
+        let drop_iterations = steps.iterations();
+        let mut num_frags: usize = 0;
+        let mut complete = false;
+        let mut cur = rx_cur;
+        while !complete && rcvd_pkts as usize + num_frags < num_rx_descs as usize {
+            body_invariant!(cur < num_rx_descs);
+            body_invariant!(rcvd_pkts as usize + num_frags < num_rx_descs as usize);
+            body_invariant!(drop_iterations >= iterations && drop_iterations - iterations <= limit);
+            body_invariant!(steps.iterations_within(drop_iterations, num_frags));
+            body_invariant!(steps.register_writes() == old(steps.register_writes()));
+            steps.count_iteration();
+            let desc = index_mut(rx_descs, cur as usize);
+            if !desc.rx_metadata().0 {
+                break;
+            }
+            num_frags += 1;
+            complete = desc.end_of_packet();
+            cur = (cur + 1) % num_rx_descs;
+        }
+
+        if complete {
+            #[cfg_attr(not(prusti), allow(unused_variables))]
+            let rearm_iterations = steps.iterations();
+            let mut j = 0;
+            while j < num_frags {
+                body_invariant!(j < num_frags);
+                body_invariant!(rcvd_pkts as usize + num_frags <= num_rx_descs as usize);
+                body_invariant!(rearm_iterations >= iterations && rearm_iterations - iterations <= limit + num_rx_descs as usize);
+                body_invariant!(steps.iterations_within(rearm_iterations, j));
+                body_invariant!(steps.register_writes() == old(steps.register_writes()));
+                body_invariant!(num_rx_descs as usize == rx_descs.len());
+                body_invariant!(rx_bufs_in_use.len() == rx_descs.len());
+                body_invariant!(rx_cur < num_rx_descs);
+                body_invariant!(rx_cur as usize == rx_cur_total % num_rx_descs as usize);
+                body_invariant!(*rx_cur_stored as usize + rcvd_pkts as usize + j == rx_cur_total);
+                body_invariant!(regs.rdt_model() == old(regs.rdt_model()));
+                steps.count_iteration();
+                let desc = index_mut(rx_descs, rx_cur as usize);
+                desc.set_packet_address(pool.data_paddr(rx_bufs_in_use.index(rx_cur as usize)));
+                rx_cur_total += 1;
+                last_rx_cur = rx_cur;
+                rx_cur = (rx_cur + 1) % num_rx_descs;
+                j += 1;
+            }
+        }
+    }
+
+    // the tail is only moved if we gave at least one descriptor back to the NIC,
+    // and then points to the last descriptor we gave back, one behind the next descriptor to check.
+    if rx_cur_total != *rx_cur_stored as usize {
+        *rx_cur_stored = (rx_cur_total % num_rx_descs as usize) as u16;
+        regs.regs.ptr.rdt.write(last_rx_cur as u32); 
+        steps.count_register_write();
+    }
+
+    if multi_desc {
+        return Err(DriverError::MultiDescriptorUnsupported);
+    }
+    Ok(RxBatchOutcome { received: rcvd_pkts, stopped_reason, unreplenished })
 }
 
 
@@ -100,63 +547,111 @@ fn index_mut<T>(s: &mut [T], index: usize) -> &mut T {
 }
 
 #[trusted]
-#[ensures(old(dest).phys_addr.value() == result.phys_addr.value())]
-#[after_expiry(dest.phys_addr.value() == src.phys_addr.value())]
+#[ensures(result.snapshot() == old(dest.snapshot()))]
+#[after_expiry(dest.snapshot() == src.snapshot())]
 fn replace(dest: &mut PacketBuffer, src: PacketBuffer) -> PacketBuffer{
     core::mem::replace(dest, src)
 }
 
 
+/// Cleans the descriptors the NIC is done with, moving the buffers whose last reference was dropped into `used_buffers`,
+/// then sends up to `batch_size` packets from `buffers`.
+/// Returns the number of packets sent and the number of descriptors that were cleaned.
+///
+/// Returns `InvalidRingState` without touching the ring if the NIC reports a head outside of it,
+/// and `QueueFull` if there are packets to send but the ring has no free descriptor for the first one.
+///
+/// At most `max_clean` descriptors are cleaned and one lap of the ring is sent per call, see [`batch_limit`].
+/// `steps` counts at most that many loop iterations and at most one register write.
 #[requires(tx_descs.len() > 0)]
 #[requires((num_tx_descs as usize) == tx_descs.len())]
 #[requires(0 <= *tx_cur_stored && *tx_cur_stored < num_tx_descs)]
-#[ensures(result.is_ok() ==> (old(*tx_cur_stored) + peek_result(&result).0) % num_tx_descs == *tx_cur_stored)]
+#[requires(0 <= *tx_clean_stored && *tx_clean_stored < num_tx_descs)]
+#[requires(used_buffers.len() + num_tx_descs as usize <= VEC_CAPACITY)]
+#[ensures(*tx_cur_stored < num_tx_descs)]
+#[ensures(*tx_clean_stored < num_tx_descs)]
+#[ensures(result.is_ok() ==> (old(*tx_cur_stored) as usize + peek_result(&result).0 as usize) % num_tx_descs as usize == *tx_cur_stored as usize)]
 #[ensures(result.is_ok()  ==> buffers.len() == old(buffers.len()) - peek_result(&result).0 as usize)]
 #[ensures(result.is_ok()  ==> tx_bufs_in_use.len() == old(tx_bufs_in_use.len()) - peek_result(&result).1 + peek_result(&result).0 as usize)]
-#[ensures(result.is_ok()  ==> used_buffers.len() == old(used_buffers.len()) + peek_result(&result).1 )]
-#[after_expiry(result.is_ok() ==> forall (|i: usize| 0<= i && i < peek_result(&result).0 as usize ==> {
-    let pkts_removed = peek_result(&result).1;
-    let tx_bufs_length_old = old(tx_bufs_in_use.len()) - pkts_removed;
-    old(buffers.index(buffers.len() - i)).phys_addr.value() == tx_bufs_in_use.index(tx_bufs_length_old + i).phys_addr.value()
-}))]
-fn tx_batch(
+#[ensures(used_buffers.len() >= old(used_buffers.len()))]
+#[ensures(result.is_ok()  ==> used_buffers.len() <= old(used_buffers.len()) + peek_result(&result).1 )]
+#[ensures((result.is_ok() && peek_result(&result).0 != 0) ==> regs.tdt_model() == *tx_cur_stored as u32)]
+#[ensures((result.is_err() || peek_result(&result).0 == 0) ==> regs.tdt_model() == old(regs.tdt_model()))]
+#[ensures(result.is_err() ==> peek_err(&result) == DriverError::InvalidRingState || peek_err(&result) == DriverError::QueueFull)]
+#[ensures(result.is_err() ==> buffers.len() == old(buffers.len()) && *tx_cur_stored == old(*tx_cur_stored))]
+#[ensures(result.is_err() ==> tx_bufs_in_use.len() <= old(tx_bufs_in_use.len()))]
+#[ensures(result.is_err() ==> used_buffers.len() - old(used_buffers.len()) <= old(tx_bufs_in_use.len()) - tx_bufs_in_use.len())]
+#[ensures((result.is_err() && peek_err(&result) == DriverError::InvalidRingState) ==> used_buffers.len() == old(used_buffers.len()))]
+#[ensures((result.is_err() && peek_err(&result) == DriverError::QueueFull) ==> batch_size > 0 && buffers.len() > 0)]
+#[ensures(result.is_ok() ==> peek_result(&result).0 as usize <= batch_limit(batch_size, num_tx_descs))]
+#[ensures(result.is_ok() ==> peek_result(&result).1 <= max_clean as usize)]
+#[ensures(steps.iterations_within(old(steps.iterations()), max_clean as usize + batch_limit(batch_size, num_tx_descs)))]
+#[ensures(steps.register_writes_within(old(steps.register_writes()), 1))]
+#[after_expiry(result.is_ok() ==> buffers.view() == seq_subsequence(old(buffers.view()), 0, buffers.len()))]
+#[after_expiry(result.is_ok() ==> tx_bufs_in_use.view() == seq_subsequence(old(tx_bufs_in_use.view()), peek_result(&result).1, old(tx_bufs_in_use.len()))
+    .concat(seq_reverse(seq_subsequence(old(buffers.view()), buffers.len(), old(buffers.len())), old(buffers.len()) - buffers.len())))]
+pub fn tx_batch<P: DmaMemory, const N: usize>(
     tx_descs: &mut [AdvancedTxDescriptor], 
-    tx_bufs_in_use: &mut VecWrapper<PacketBuffer>,
+    tx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
     num_tx_descs: u16,
     tx_clean_stored: &mut u16,
     tx_cur_stored: &mut u16,
     regs: &mut TxQueueRegisters,
     batch_size: usize,  
     buffers: &mut VecWrapper<PacketBuffer>, 
-    used_buffers: &mut VecWrapper<PacketBuffer>
-) -> Result<(u16, usize), &'static str> {
-    let pkts_removed = tx_clean(tx_descs, tx_bufs_in_use, tx_clean_stored, tx_cur_stored, num_tx_descs, used_buffers);
+    used_buffers: &mut VecWrapper<PacketBuffer>,
+    pool: &mut Mempool<P>,
+    max_clean: u16,
+    steps: &mut StepCounter
+) -> Result<(u16, usize), DriverError> {
+    let head = regs.tdh.read();
+    if head >= num_tx_descs as u32 {
+        return Err(DriverError::InvalidRingState);
+    }
+    let pkts_removed = tx_clean(tx_descs, tx_bufs_in_use, tx_clean_stored, num_tx_descs, used_buffers, pool, head, max_clean, steps);
+    let limit = batch_limit(batch_size, num_tx_descs);
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let iterations = steps.iterations();
     
     let mut pkts_sent = 0;
     let tx_clean = *tx_clean_stored;
     let mut tx_cur = *tx_cur_stored;
 
     // have to add this in for verification because the verifier can't reason that taking the remainder after each increment, 
-    // or taking the remainder after all increments is equivalent
-    let mut tx_cur_total = *tx_cur_stored; 
+    // or taking the remainder after all increments is equivalent.
+    // It can go past the end of the ring, so it is a `usize` to not overflow with a ring of `u16::MAX` descriptors.
+    let mut tx_cur_total = *tx_cur_stored as usize; 
 
 
     // debug!("tx_cur = {}, tx_clean ={}", tx_cur, tx_clean);
     
+    #[cfg_attr(not(prusti), allow(unused_variables))]
     let buffers_len = buffers.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
     let buffers_in_use_len = tx_bufs_in_use.len();
 
+    let mut queue_full = false;
     let mut i = 0;
-    while i < batch_size {
+    while i < limit {
+        body_invariant!(i < limit);
+        body_invariant!(steps.iterations_within(iterations, i));
+        body_invariant!(steps.register_writes() == old(steps.register_writes()));
+        body_invariant!(!queue_full);
         body_invariant!(num_tx_descs as usize == tx_descs.len());
         body_invariant!(tx_cur < num_tx_descs);
         body_invariant!(pkts_sent as usize == i);
-        body_invariant!(*tx_cur_stored + pkts_sent == tx_cur_total);
+        body_invariant!(*tx_cur_stored as usize + pkts_sent as usize == tx_cur_total);
+        body_invariant!(tx_cur as usize == tx_cur_total % num_tx_descs as usize);
+        body_invariant!(regs.tdt_model() == old(regs.tdt_model()));
         // // body_invariant!((rx_cur == rx_cur_total) || (rx_cur_total % num_rx_descs == rx_cur));
         body_invariant!(buffers.len() == buffers_len - pkts_sent as usize);
         body_invariant!(tx_bufs_in_use.len() == buffers_in_use_len + pkts_sent as usize);
+        body_invariant!(buffers.view() == seq_subsequence(old(buffers.view()), 0, buffers.len()));
+        body_invariant!(tx_bufs_in_use.view() == seq_subsequence(old(tx_bufs_in_use.view()), pkts_removed, old(tx_bufs_in_use.len()))
+            .concat(seq_reverse(seq_subsequence(old(buffers.view()), buffers.len(), buffers_len), pkts_sent as usize)));
         // body_invariant!(tx_cur == (*tx_cur_stored + pkts_sent) % num_tx_descs);
 
+        steps.count_iteration();
         if let Some(packet) = buffers.pop() {
             let tx_next = (tx_cur + 1) % num_tx_descs;
 
@@ -164,11 +659,20 @@ fn tx_batch(
                 // tx queue of device is full, push packet back onto the
                 // queue of to-be-sent packets
                 buffers.push(packet);
+                queue_full = pkts_sent == 0;
+                break;
+            }
+
+            if tx_bufs_in_use.is_full() {
+                // the ring is larger than the capacity of the queue, so treat it as full
+                buffers.push(packet);
+                queue_full = pkts_sent == 0;
                 break;
             }
 
-            index_mut(tx_descs, tx_cur as usize).send(packet.phys_addr, packet.length);
-            tx_bufs_in_use.push(packet);
+            let (paddr, length) = pool.buffer_metadata(&packet);
+            index_mut(tx_descs, tx_cur as usize).send(paddr, length);
+            tx_bufs_in_use.push_back(packet);
 
             tx_cur = tx_next;
             pkts_sent += 1;
@@ -181,47 +685,485 @@ fn tx_batch(
     }
 
 
-    // *tx_cur_stored = tx_cur;
-    *tx_cur_stored = tx_cur_total % num_tx_descs;
-    regs.tdt.write(tx_cur as u32);
+    // the tail is only moved if we handed at least one new descriptor to the NIC
+    if pkts_sent != 0 {
+        *tx_cur_stored = (tx_cur_total % num_tx_descs as usize) as u16;
+        regs.regs.ptr.tdt.write(tx_cur as u32);
+        steps.count_register_write();
+    }
 
+    if queue_full {
+        return Err(DriverError::QueueFull);
+    }
     Ok((pkts_sent, pkts_removed))
 }
 
  /// Removes multiples of `TX_CLEAN_BATCH` packets from `queue`.    
 /// (code taken from https://github.com/ixy-languages/ixy.rs/blob/master/src/ixgbe.rs#L1016)
-#[trusted]
+/// `head` is the value of TDH, which the caller has checked to lie inside the ring.
+/// The handle of every removed buffer is released, and only the buffers for which it was the last reference
+/// are moved into `used_buffers`, so a buffer sent on several queues is only handed back once.
+/// At most `max_clean` buffers are removed, and `tx_clean_stored` only moves past the descriptors of the removed ones.
+#[requires(*tx_clean_stored < num_tx_descs)]
+#[requires((num_tx_descs as usize) == tx_descs.len())]
+#[requires(head < num_tx_descs as u32)]
+#[requires(used_buffers.len() + num_tx_descs as usize <= VEC_CAPACITY)]
+#[ensures(*tx_clean_stored < num_tx_descs)]
+#[ensures(result <= max_clean as usize)]
+#[ensures(result < num_tx_descs as usize)]
+#[ensures(steps.iterations_within(old(steps.iterations()), result))]
+#[ensures(steps.register_writes() == old(steps.register_writes()))]
 #[ensures(old(tx_bufs_in_use.len()) == tx_bufs_in_use.len() + result)]
-#[ensures(old(used_buffers.len()) + result == used_buffers.len())]
-#[after_expiry( forall (|i: usize| 0 <= i && i < result as usize ==> {
-    let old_used_buffer_len = old(used_buffers.len());
-    used_buffers.index(old_used_buffer_len + i).phys_addr.value() == old(tx_bufs_in_use.index(i)).phys_addr.value()
-}))]
-fn tx_clean(    
+#[ensures(tx_bufs_in_use.view() == seq_subsequence(old(tx_bufs_in_use.view()), result, old(tx_bufs_in_use.len())))]
+#[ensures(used_buffers.len() >= old(used_buffers.len()))]
+#[ensures(used_buffers.len() <= old(used_buffers.len()) + result)]
+#[ensures(pool.free_count() == old(pool.free_count()))]
+fn tx_clean<P: DmaMemory, const N: usize>(
     tx_descs: &[AdvancedTxDescriptor], 
-    tx_bufs_in_use: &mut VecWrapper<PacketBuffer>,
+    tx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
     tx_clean_stored: &mut u16, 
-    tx_cur_stored: &u16, 
     num_tx_descs: u16, 
     used_buffers: &mut VecWrapper<PacketBuffer>,
-    head: u32
+    pool: &mut Mempool<P>,
+    head: u32,
+    max_clean: u16,
+    steps: &mut StepCounter
 )  -> usize {
+    let pkts_removed = num_cleanable(tx_descs, tx_bufs_in_use.len(), *tx_clean_stored, num_tx_descs, head, max_clean);
+    if pkts_removed == 0 {
+        return 0;
+    }
+
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let in_use_len = tx_bufs_in_use.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let used_len = used_buffers.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let free_count = pool.free_count();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let iterations = steps.iterations();
+    let mut i = 0;
+    while i < pkts_removed {
+        body_invariant!(i < pkts_removed);
+        body_invariant!(steps.iterations_within(iterations, i));
+        body_invariant!(steps.register_writes() == old(steps.register_writes()));
+        body_invariant!(tx_bufs_in_use.len() == in_use_len - i);
+        body_invariant!(tx_bufs_in_use.view() == seq_subsequence(old(tx_bufs_in_use.view()), i, in_use_len));
+        body_invariant!(used_buffers.len() >= used_len && used_buffers.len() <= used_len + i);
+        body_invariant!(used_len + num_tx_descs as usize <= VEC_CAPACITY);
+        body_invariant!(pool.free_count() == free_count);
+
+        steps.count_iteration();
+        if let Some(buffer) = tx_bufs_in_use.pop_front() {
+            if let Some(last) = pool.release_ref(buffer) {
+                used_buffers.push(last);
+            }
+        }
+        i += 1;
+    }
+    
+    *tx_clean_stored = next_clean(*tx_clean_stored, pkts_removed, num_tx_descs);
+    pkts_removed
+}
+
+/// Cleans the descriptors the NIC is done with like [`tx_batch`] does before sending, but releases the removed buffers
+/// straight back to `pool` instead of handing them to the caller.
+/// Returns the number of buffers removed from `tx_bufs_in_use` and the number of them that were freed,
+/// which only differ if some buffers are still referenced through handles from [`Mempool::clone_ref`].
+///
+/// Returns `InvalidRingState` without touching the ring if the NIC reports a head outside of it.
+/// At most `max_clean` buffers are removed, and `steps` counts one loop iteration for each of them.
+#[requires(*tx_clean_stored < num_tx_descs)]
+#[requires((num_tx_descs as usize) == tx_descs.len())]
+#[ensures(*tx_clean_stored < num_tx_descs)]
+#[ensures(result.is_ok() ==> peek_result(&result).0 <= max_clean as usize)]
+#[ensures(steps.iterations_within(old(steps.iterations()), max_clean as usize))]
+#[ensures(steps.register_writes() == old(steps.register_writes()))]
+#[ensures(result.is_ok() ==> tx_bufs_in_use.len() == old(tx_bufs_in_use.len()) - peek_result(&result).0)]
+#[ensures(result.is_ok() ==> tx_bufs_in_use.view() == seq_subsequence(old(tx_bufs_in_use.view()), peek_result(&result).0, old(tx_bufs_in_use.len())))]
+#[ensures(result.is_ok() ==> pool.free_count() == old(pool.free_count()) + peek_result(&result).1)]
+#[ensures(result.is_ok() ==> peek_result(&result).1 <= peek_result(&result).0)]
+#[ensures(result.is_err() ==> peek_err(&result) == DriverError::InvalidRingState)]
+#[ensures(result.is_err() ==> tx_bufs_in_use.len() == old(tx_bufs_in_use.len()) && pool.free_count() == old(pool.free_count()))]
+pub fn tx_clean_into_pool<P: DmaMemory, const N: usize>(
+    tx_descs: &[AdvancedTxDescriptor],
+    tx_bufs_in_use: &mut RingBuf<PacketBuffer, N>,
+    tx_clean_stored: &mut u16,
+    num_tx_descs: u16,
+    regs: &TxQueueRegisters,
+    pool: &mut Mempool<P>,
+    max_clean: u16,
+    steps: &mut StepCounter
+) -> Result<(usize, usize), DriverError> {
+    let head = regs.tdh.read();
+    if head >= num_tx_descs as u32 {
+        return Err(DriverError::InvalidRingState);
+    }
+
+    let pkts_removed = num_cleanable(tx_descs, tx_bufs_in_use.len(), *tx_clean_stored, num_tx_descs, head, max_clean);
+    if pkts_removed == 0 {
+        return Ok((0, 0));
+    }
+
+    let pkts_freed = release_front(tx_bufs_in_use, pkts_removed, pool, steps);
+    *tx_clean_stored = next_clean(*tx_clean_stored, pkts_removed, num_tx_descs);
+    Ok((pkts_removed, pkts_freed))
+}
+
+/// The number of buffers at the front of `tx_bufs_in_use` that can be cleaned, given the head reported by the NIC.
+/// Buffers are only cleaned in multiples of `TX_CLEAN_BATCH`, so this is 0 until enough packets have been sent,
+/// and then at most `max_clean` of them.
+/// TDH can move past a descriptor before the NIC has written it back, so nothing is cleaned
+/// unless the last descriptor to clean has its DD bit set. Every descriptor is sent with `TX_CMD_RS`
+/// and the NIC writes them back in order, so the earlier ones are done as well.
+#[requires(tx_clean < num_tx_descs)]
+#[requires((num_tx_descs as usize) == tx_descs.len())]
+#[requires(head < num_tx_descs as u32)]
+#[ensures(result <= tx_bufs_in_use_len)]
+#[ensures(result <= max_clean as usize)]
+#[ensures(result < num_tx_descs as usize)]
+fn num_cleanable(tx_descs: &[AdvancedTxDescriptor], tx_bufs_in_use_len: usize, tx_clean: u16, num_tx_descs: u16, head: u32, max_clean: u16) -> usize {
     const TX_CLEAN_BATCH: u16 = 64;
     let head = head as u16;
-    let pkts_removed;
-    let cleanable = (head as i32 - *tx_clean_stored as i32) as u16 & (num_tx_descs - 1);
+    // the distance from `tx_clean` forward to `head`, going around the end of the ring if necessary
+    let cleanable = if head >= tx_clean { head - tx_clean } else { num_tx_descs - (tx_clean - head) };
     if cleanable < TX_CLEAN_BATCH {
         return 0;
     }
 
-    if cleanable as usize >= tx_bufs_in_use.len() {
-        used_buffers.v.extend(tx_bufs_in_use.v.drain(..));
-        pkts_removed = tx_bufs_in_use.len();
+    let cleanable = if cleanable as usize >= tx_bufs_in_use_len {
+        tx_bufs_in_use_len
     } else {
-        used_buffers.v.extend(tx_bufs_in_use.v.drain(..cleanable as usize));
-        pkts_removed = cleanable as usize;
+        cleanable as usize
     };
-    
-    *tx_clean_stored = head;
-    pkts_removed
+    let cleanable = if cleanable > max_clean as usize { max_clean as usize } else { cleanable };
+    if cleanable == 0 || !tx_descs[next_clean(tx_clean, cleanable - 1, num_tx_descs) as usize].desc_done() {
+        return 0;
+    }
+    cleanable
+}
+
+/// The first descriptor that is not yet cleaned after cleaning `pkts_removed` descriptors from `tx_clean` on,
+/// one for each removed buffer.
+#[requires(tx_clean < num_tx_descs)]
+#[requires(pkts_removed < num_tx_descs as usize)]
+#[ensures(result < num_tx_descs)]
+fn next_clean(tx_clean: u16, pkts_removed: usize, num_tx_descs: u16) -> u16 {
+    ((tx_clean as usize + pkts_removed) % num_tx_descs as usize) as u16
+}
+
+/// Releases the first `n` buffers of `buffers` to `pool` and returns the number of them that were freed.
+#[requires(n <= buffers.len())]
+#[ensures(buffers.len() == old(buffers.len()) - n)]
+#[ensures(buffers.view() == seq_subsequence(old(buffers.view()), n, old(buffers.len())))]
+#[ensures(result <= n)]
+#[ensures(pool.free_count() == old(pool.free_count()) + result)]
+#[ensures(steps.iterations_within(old(steps.iterations()), n))]
+#[ensures(steps.register_writes() == old(steps.register_writes()))]
+fn release_front<P: DmaMemory, const N: usize>(
+    buffers: &mut RingBuf<PacketBuffer, N>,
+    n: usize,
+    pool: &mut Mempool<P>,
+    steps: &mut StepCounter
+) -> usize {
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let len = buffers.len();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let free_count = pool.free_count();
+    #[cfg_attr(not(prusti), allow(unused_variables))]
+    let iterations = steps.iterations();
+    let mut freed = 0;
+    let mut i = 0;
+    while i < n {
+        body_invariant!(i < n);
+        body_invariant!(steps.iterations_within(iterations, i));
+        body_invariant!(steps.register_writes() == old(steps.register_writes()));
+        body_invariant!(buffers.len() == len - i);
+        body_invariant!(buffers.view() == seq_subsequence(old(buffers.view()), i, len));
+        body_invariant!(freed <= i);
+        body_invariant!(pool.free_count() == free_count + freed);
+
+        steps.count_iteration();
+        if let Some(buffer) = buffers.pop_front() {
+            if pool.release(buffer) {
+                freed += 1;
+            }
+        }
+        i += 1;
+    }
+    freed
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::dma::{HeapDma, HeapDmaAllocator};
+    use crate::structs::tests::{pool, rx_regs, tx_regs};
+
+    const NUM_DESCS: usize = 8;
+
+    /// A receive queue of `NUM_DESCS` descriptors, each given a buffer from `pool`.
+    fn rx_queue(pool: &mut Mempool<HeapDma>) -> RxQueue<HeapDma, NUM_DESCS> {
+        let rx_descs = DescriptorRing::new(&mut HeapDmaAllocator, NUM_DESCS).unwrap();
+        let mut rx_bufs_in_use = RingBuf::new();
+        for _ in 0..NUM_DESCS {
+            rx_bufs_in_use.push_back(pool.pop().unwrap());
+        }
+        RxQueue::new(0, rx_regs(), rx_descs, rx_bufs_in_use, pool)
+    }
+
+    fn tx_queue() -> TxQueue<HeapDma, NUM_DESCS> {
+        TxQueue::new(0, tx_regs(), DescriptorRing::new(&mut HeapDmaAllocator, NUM_DESCS).unwrap())
+    }
+
+    /// Does what the NIC does once it has written a packet of `length` bytes into the buffer of `rx_descs[i]`.
+    fn write_back(queue: &mut RxQueue<HeapDma, NUM_DESCS>, i: usize, length: u16, end_of_packet: bool) {
+        let status = RX_STATUS_DD | if end_of_packet { RX_STATUS_EOP } else { 0 };
+        let metadata = (length as u64) << RX_WB_PKT_LEN_START | status as u64;
+        queue.rx_descs.descs_mut()[i].header_buffer_address.write(metadata);
+    }
+
+    #[test]
+    fn new_rx_queue_points_every_descriptor_at_its_buffer() {
+        let mut pool = pool(2 * NUM_DESCS, 64);
+        let queue = rx_queue(&mut pool);
+        assert_eq!((queue.rx_cur, queue.num_rx_descs as usize), (0, NUM_DESCS));
+        for (i, desc) in queue.rx_descs.descs().iter().enumerate() {
+            let buffer = queue.rx_bufs_in_use.index(i);
+            assert_eq!(desc.packet_buffer_address.read(), pool.data_paddr(buffer).value() as u64);
+            assert_eq!(desc.header_buffer_address.read(), 0);
+        }
+    }
+
+    #[test]
+    fn new_tx_queue_is_empty() {
+        let queue = tx_queue();
+        assert_eq!((queue.tx_cur, queue.tx_clean, queue.num_tx_descs as usize), (0, 0, NUM_DESCS));
+        assert!(queue.tx_bufs_in_use.is_empty());
+        assert_eq!(queue.max_clean(), u16::MAX);
+    }
+
+    #[test]
+    fn rx_batch_stops_at_the_first_empty_descriptor_or_a_full_batch() {
+        let mut pool = pool(2 * NUM_DESCS, 64);
+        let mut queue = rx_queue(&mut pool);
+        let mut buffers = VecWrapper::new();
+
+        let outcome = queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::RingEmpty);
+        assert_eq!((outcome.received, outcome.unreplenished, outcome.error()), (0, 0, None));
+        // nothing was given back to the NIC, so the tail isn't written
+        assert_eq!((queue.regs.regs.ptr.rdt.read(), queue.steps().register_writes()), (0, 0));
+
+        for i in 0..3 {
+            write_back(&mut queue, i, 100 + i as u16, true);
+        }
+        let received: [usize; 3] = core::array::from_fn(|i| queue.rx_bufs_in_use.index(i).index());
+        let outcome = queue.rx_batch(&mut buffers, 2, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::BatchFull);
+        assert_eq!((outcome.received, queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (2, 2, 1));
+
+        let outcome = queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::RingEmpty);
+        assert_eq!((outcome.received, queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (1, 3, 2));
+
+        // the packets come out in the order of the ring, with the lengths the NIC wrote back
+        for (i, index) in received.into_iter().enumerate() {
+            let buffer = buffers.index(i);
+            assert_eq!((buffer.index(), pool.get_length(buffer)), (index, 100 + i as u16));
+        }
+        assert_eq!(pool.free_count(), NUM_DESCS - 3);
+    }
+
+    #[test]
+    fn rx_batch_counts_the_descriptors_left_for_lack_of_buffers() {
+        let mut pool = pool(NUM_DESCS + 2, 64);
+        let mut queue = rx_queue(&mut pool);
+        for i in 0..5 {
+            write_back(&mut queue, i, 60, true);
+        }
+        let mut buffers = VecWrapper::new();
+        let outcome = queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::PoolExhausted);
+        assert_eq!((outcome.received, outcome.unreplenished, buffers.len()), (2, 3, 2));
+        assert_eq!(outcome.error(), Some(DriverError::PoolExhausted));
+        assert_eq!((pool.free_count(), queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (0, 2, 1));
+        // the scan for filled descriptors counts as iterations too, but stays within the batch
+        assert_eq!(queue.steps().iterations(), 6);
+        // the descriptors that were received from now point at the buffers the pool had left
+        for i in 0..2 {
+            let desc = &queue.rx_descs.descs()[i];
+            assert_eq!(desc.packet_buffer_address.read(), pool.data_paddr(queue.rx_bufs_in_use.index(i)).value() as u64);
+            assert_eq!(desc.header_buffer_address.read(), 0);
+        }
+
+        // once there are buffers again, the packets that were left in the ring are received
+        while let Some(buffer) = buffers.pop() {
+            pool.push(buffer);
+        }
+        let outcome = queue.rx_batch(&mut buffers, 2, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::BatchFull);
+        assert_eq!((outcome.received, outcome.unreplenished, pool.free_count()), (2, 0, 0));
+
+        // and the buffers that were reserved but not needed go back to the pool
+        while let Some(buffer) = buffers.pop() {
+            pool.push(buffer);
+        }
+        let outcome = queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap();
+        assert_eq!(outcome.stopped_reason, RxStopReason::RingEmpty);
+        assert_eq!((outcome.received, buffers.len(), pool.free_count(), queue.rx_cur), (1, 1, 1, 5));
+    }
+
+    #[test]
+    fn rx_batch_drops_a_split_packet_once_it_is_complete() {
+        let mut pool = pool(2 * NUM_DESCS, 64);
+        let mut queue = rx_queue(&mut pool);
+        let mut buffers = VecWrapper::new();
+        write_back(&mut queue, 0, 60, true);
+        write_back(&mut queue, 1, 2048, false);
+        write_back(&mut queue, 2, 2048, false);
+
+        // the packet in front of the split one is still received
+        assert_eq!(queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap_err(), DriverError::MultiDescriptorUnsupported);
+        assert_eq!((buffers.len(), queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (1, 1, 0));
+        // the NIC hasn't written back the last descriptor of the split packet yet, so it stays in the ring
+        let frags: [usize; 3] = core::array::from_fn(|i| queue.rx_bufs_in_use.index(i + 1).index());
+        assert_eq!(queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap_err(), DriverError::MultiDescriptorUnsupported);
+        assert_eq!((buffers.len(), queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (1, 1, 0));
+
+        write_back(&mut queue, 3, 100, true);
+        let free_count = pool.free_count();
+        assert_eq!(queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap_err(), DriverError::MultiDescriptorUnsupported);
+        assert_eq!((buffers.len(), queue.rx_cur, queue.regs.regs.ptr.rdt.read()), (1, 4, 3));
+        // its descriptors are given back to the NIC with the buffers they already held
+        assert_eq!(pool.free_count(), free_count);
+        for i in 1..4 {
+            let buffer = queue.rx_bufs_in_use.index(i);
+            let desc = &queue.rx_descs.descs()[i];
+            assert_eq!(buffer.index(), frags[i - 1]);
+            assert_eq!(desc.packet_buffer_address.read(), pool.data_paddr(buffer).value() as u64);
+            assert_eq!(desc.header_buffer_address.read(), 0);
+        }
+
+        // and the next packet is received normally
+        write_back(&mut queue, 4, 60, true);
+        let outcome = queue.rx_batch(&mut buffers, NUM_DESCS, &mut pool).unwrap();
+        assert_eq!((outcome.received, buffers.len(), queue.rx_cur), (1, 2, 5));
+    }
+
+    #[test]
+    fn tx_batch_reports_a_full_ring_and_a_head_outside_of_it() {
+        let mut pool = pool(2 * NUM_DESCS, 64);
+        let mut queue = tx_queue();
+        let mut buffers = VecWrapper::new();
+        let mut used_buffers = VecWrapper::new();
+        for _ in 0..NUM_DESCS + 2 {
+            let buffer = pool.pop().unwrap();
+            assert!(pool.append(&buffer, 60));
+            buffers.push(buffer);
+        }
+
+        // one descriptor is always left empty, so that a full ring can be told apart from an empty one
+        assert_eq!(queue.tx_batch(NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool), Ok((NUM_DESCS as u16 - 1, 0)));
+        assert_eq!((buffers.len(), queue.tx_bufs_in_use.len(), queue.tx_cur), (3, NUM_DESCS - 1, 7));
+        assert_eq!(queue.regs.regs.ptr.tdt.read(), 7);
+        for i in 0..NUM_DESCS - 1 {
+            let desc = &queue.tx_descs.descs()[i];
+            let buffer = queue.tx_bufs_in_use.index(i);
+            assert_eq!((desc.packet_buffer_address.read(), desc.data_len.read()), (pool.data_paddr(buffer).value() as u64, 60));
+        }
+
+        assert_eq!(queue.tx_batch(NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool), Err(DriverError::QueueFull));
+        assert_eq!((buffers.len(), queue.tx_cur, queue.regs.regs.ptr.tdt.read()), (3, 7, 7));
+        // with nothing to send, a full ring isn't an error
+        assert_eq!(queue.tx_batch(0, &mut buffers, &mut used_buffers, &mut pool), Ok((0, 0)));
+
+        queue.regs.regs.ptr.tdh.write(NUM_DESCS as u32);
+        assert_eq!(queue.tx_batch(NUM_DESCS, &mut buffers, &mut used_buffers, &mut pool), Err(DriverError::InvalidRingState));
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Err(DriverError::InvalidRingState));
+        assert_eq!((buffers.len(), queue.tx_bufs_in_use.len(), used_buffers.len()), (3, NUM_DESCS - 1, 0));
+        assert_eq!(queue.steps().register_writes(), 1);
+    }
+
+    /// Does what the NIC does once it has sent the packet of `tx_descs[i]`.
+    fn tx_write_back<const N: usize>(queue: &mut TxQueue<HeapDma, N>, i: usize) {
+        queue.tx_descs.descs_mut()[i].paylen_popts_cc_idx_sta.write(TX_STATUS_DD as u32);
+    }
+
+    #[test]
+    fn tx_clean_into_pool_frees_the_sent_buffers_without_other_handles() {
+        // descriptors are only cleaned 64 at a time, so the ring has to be larger than that
+        const NUM_TX_DESCS: usize = 128;
+        let mut pool = pool(NUM_TX_DESCS, 0);
+        let mut queue: TxQueue<HeapDma, NUM_TX_DESCS> =
+            TxQueue::new(0, tx_regs(), DescriptorRing::new(&mut HeapDmaAllocator, NUM_TX_DESCS).unwrap());
+        let mut buffers = VecWrapper::new();
+        let mut used_buffers = VecWrapper::new();
+        for _ in 0..100 {
+            buffers.push(pool.pop().unwrap());
+        }
+        // the first buffer that is sent is still referenced elsewhere, e.g. because it is sent on another queue too
+        let shared = pool.clone_ref(buffers.index(99)).unwrap();
+        assert_eq!(queue.tx_batch(100, &mut buffers, &mut used_buffers, &mut pool), Ok((100, 0)));
+        assert_eq!(pool.free_count(), NUM_TX_DESCS - 100);
+
+        // fewer descriptors than a cleaning batch are done
+        queue.regs.regs.ptr.tdh.write(50);
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((0, 0)));
+        // TDH has moved on, but the NIC hasn't written back the last descriptor to clean yet
+        queue.regs.regs.ptr.tdh.write(80);
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((0, 0)));
+
+        tx_write_back(&mut queue, 79);
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((80, 79)));
+        assert_eq!((pool.free_count(), queue.tx_bufs_in_use.len(), queue.tx_clean), (NUM_TX_DESCS - 21, 20, 80));
+        assert_eq!(pool.refcount(&shared), 1);
+        assert!(pool.release(shared));
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((0, 0)));
+        assert_eq!(queue.steps().iterations(), 100 + 80);
+    }
+
+    #[test]
+    fn max_clean_caps_the_buffers_cleaned_per_call() {
+        const NUM_TX_DESCS: usize = 128;
+        let mut pool = pool(NUM_TX_DESCS, 0);
+        let mut queue: TxQueue<HeapDma, NUM_TX_DESCS> =
+            TxQueue::new(0, tx_regs(), DescriptorRing::new(&mut HeapDmaAllocator, NUM_TX_DESCS).unwrap());
+        let mut buffers = VecWrapper::new();
+        let mut used_buffers = VecWrapper::new();
+        for _ in 0..100 {
+            buffers.push(pool.pop().unwrap());
+        }
+        assert_eq!(queue.tx_batch(100, &mut buffers, &mut used_buffers, &mut pool), Ok((100, 0)));
+        for i in 0..100 {
+            tx_write_back(&mut queue, i);
+        }
+        queue.regs.regs.ptr.tdh.write(100);
+        queue.set_max_clean(70);
+        queue.reset_steps();
+
+        // tx_batch cleans up to the cap before sending, and hands the buffers to the caller
+        assert_eq!(queue.tx_batch(0, &mut buffers, &mut used_buffers, &mut pool), Ok((0, 70)));
+        assert_eq!((used_buffers.len(), queue.tx_bufs_in_use.len(), queue.tx_clean), (70, 30, 70));
+        assert_eq!((queue.steps().iterations(), queue.steps().register_writes()), (70, 0));
+
+        // the 30 that are left are fewer than a cleaning batch
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((0, 0)));
+        pool.free_bulk(&mut used_buffers);
+        assert_eq!(pool.free_count(), NUM_TX_DESCS - 30);
+
+        // tx_clean_into_pool is capped the same way, here across the end of the ring
+        for _ in 0..90 {
+            buffers.push(pool.pop().unwrap());
+        }
+        assert_eq!(queue.tx_batch(90, &mut buffers, &mut used_buffers, &mut pool), Ok((90, 0)));
+        for i in 0..NUM_TX_DESCS {
+            tx_write_back(&mut queue, i);
+        }
+        queue.regs.regs.ptr.tdh.write(60);
+        queue.set_max_clean(100);
+        queue.reset_steps();
+        assert_eq!(queue.tx_clean_into_pool(&mut pool), Ok((100, 100)));
+        assert_eq!((queue.tx_bufs_in_use.len(), queue.tx_clean, queue.steps().iterations()), (20, 42, 100));
+    }
 }
@@ -0,0 +1,45 @@
+//! A wrapper for memory-mapped registers and the fields of DMA descriptors.
+//!
+//! Every access goes through `read_volatile`/`write_volatile`, so the compiler can neither elide nor merge them.
+//! For verification, `Volatile` has a ghost `model()`: the value most recently written by software.
+//! It only exists when compiling with Prusti, so the driver can't use it to read back a register.
+//! The device may change a register at any time, so `read()` is not tied to the model,
+//! but `write()` is, which lets callers prove which value they last handed to the NIC.
+//!
+//! There are no read-only or write-only variants: every register of the receive and transmit queue blocks
+//! that the driver maps is read/write in the 82599 datasheet, including RDH/TDH, which software may only write
+//! while the queue is disabled.
+
+use prusti_contracts::*;
+
+/// A register or descriptor field which software may both read and write.
+#[repr(transparent)]
+pub struct Volatile<T: Copy> {
+    inner: T
+}
+
+impl<T: Copy> Volatile<T> {
+    /// The value most recently written by software.
+    #[cfg(prusti)]
+    #[pure]
+    #[trusted]
+    pub fn model(&self) -> T {
+        self.inner
+    }
+
+    #[inline(always)]
+    #[trusted]
+    #[ensures(snapshot_equality(self.model(), val))]
+    pub fn write(&mut self, val: T) {
+        // SAFETY: `self.inner` is a valid, aligned and exclusively borrowed `T`
+        unsafe { core::ptr::write_volatile(&mut self.inner, val) }
+    }
+
+    /// Returns the current value of the register, which may have been changed by the device since the last `write()`.
+    #[inline(always)]
+    #[trusted]
+    pub fn read(&self) -> T {
+        // SAFETY: `self.inner` is a valid and aligned `T`
+        unsafe { core::ptr::read_volatile(&self.inner) }
+    }
+}
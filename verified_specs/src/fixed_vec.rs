@@ -0,0 +1,228 @@
+//! A vector with a fixed capacity which lives inline, for the storage of `VecWrapper` when there is no allocator.
+//!
+//! It offers the subset of the `Vec` API that `VecWrapper` uses, so the bodies of `VecWrapper` are the same
+//! in both configurations. Exceeding the capacity panics, but the preconditions of `VecWrapper` keep its length
+//! within `VEC_CAPACITY`, which is the capacity of its `FixedVec`.
+//!
+//! The methods are only called from the trusted bodies of `VecWrapper`, so they are trusted as well and not specified.
+
+use core::ops::{Index, IndexMut, Range};
+use prusti_contracts::*;
+
+pub struct FixedVec<T, const N: usize> {
+    /// The slots, of which the first `len` are occupied
+    slots: [Option<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> FixedVec<T, N> {
+    #[trusted]
+    pub fn new() -> Self {
+        FixedVec { slots: core::array::from_fn(|_| None), len: 0 }
+    }
+
+    #[trusted]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[trusted]
+    pub fn push(&mut self, value: T) {
+        assert!(self.len < N, "FixedVec is full");
+        self.slots[self.len] = Some(value);
+        self.len += 1;
+    }
+
+    #[trusted]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.slots[self.len].take()
+    }
+
+    #[trusted]
+    pub fn insert(&mut self, index: usize, element: T) {
+        assert!(index <= self.len, "insertion index out of bounds");
+        self.push(element);
+        self.slots[index..self.len].rotate_right(1);
+    }
+
+    #[trusted]
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index out of bounds");
+        self.slots[index..self.len].rotate_left(1);
+        match self.pop() {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+
+    #[trusted]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        assert!(a < self.len && b < self.len, "swap index out of bounds");
+        self.slots.swap(a, b);
+    }
+
+    #[trusted]
+    pub fn reverse(&mut self) {
+        self.slots[..self.len].reverse();
+    }
+
+    #[trusted]
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    /// Moves all elements of `other` to the end of `self`.
+    #[trusted]
+    pub fn append(&mut self, other: &mut Self) {
+        for slot in other.slots[..other.len].iter_mut() {
+            if let Some(value) = slot.take() {
+                self.push(value);
+            }
+        }
+        other.len = 0;
+    }
+
+    /// Removes the elements in `range` and returns an iterator over them.
+    #[trusted]
+    pub fn drain(&mut self, range: Range<usize>) -> IntoIter<T, N> {
+        assert!(range.start <= range.end && range.end <= self.len, "drain range out of bounds");
+        let mut drained = FixedVec::new();
+        for slot in self.slots[range.clone()].iter_mut() {
+            if let Some(value) = slot.take() {
+                drained.push(value);
+            }
+        }
+        self.slots[range.start..self.len].rotate_left(range.end - range.start);
+        self.len -= range.end - range.start;
+        drained.into_iter()
+    }
+}
+
+impl<T, const N: usize> Index<usize> for FixedVec<T, N> {
+    type Output = T;
+
+    #[trusted]
+    fn index(&self, index: usize) -> &T {
+        assert!(index < self.len, "index out of bounds");
+        match &self.slots[index] {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<T, const N: usize> IndexMut<usize> for FixedVec<T, N> {
+    #[trusted]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        assert!(index < self.len, "index out of bounds");
+        match &mut self.slots[index] {
+            Some(value) => value,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for FixedVec<T, N> {
+    #[trusted]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = FixedVec::new();
+        for value in iter {
+            v.push(value);
+        }
+        v
+    }
+}
+
+impl<T, const N: usize> IntoIterator for FixedVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    #[trusted]
+    fn into_iter(self) -> IntoIter<T, N> {
+        IntoIter { v: self, next: 0 }
+    }
+}
+
+/// An iterator which moves the elements out of a [`FixedVec`], from front to back.
+pub struct IntoIter<T, const N: usize> {
+    v: FixedVec<T, N>,
+    next: usize,
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    #[trusted]
+    fn next(&mut self) -> Option<T> {
+        if self.next == self.v.len {
+            return None;
+        }
+        self.next += 1;
+        self.v.slots[self.next - 1].take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect<const N: usize>(v: impl IntoIterator<Item = u32>) -> [Option<u32>; N] {
+        let mut out = [None; N];
+        for (slot, value) in out.iter_mut().zip(v) {
+            *slot = Some(value);
+        }
+        out
+    }
+
+    #[test]
+    fn push_to_capacity() {
+        let mut v: FixedVec<u32, 3> = (0..3).collect();
+        assert_eq!(v.len(), 3);
+        assert_eq!(v.pop(), Some(2));
+        v.push(5);
+        assert_eq!(collect(v), [Some(0), Some(1), Some(5)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "FixedVec is full")]
+    fn push_past_capacity() {
+        let mut v: FixedVec<u32, 2> = (0..2).collect();
+        v.push(2);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut v: FixedVec<u32, 4> = (0..3).collect();
+        v.insert(1, 9);
+        assert_eq!(collect(v), [Some(0), Some(9), Some(1), Some(2)]);
+
+        let mut v: FixedVec<u32, 4> = (0..4).collect();
+        assert_eq!(v.remove(1), 1);
+        assert_eq!(v.len(), 3);
+        assert_eq!((v[0], v[1], v[2]), (0, 2, 3));
+        v.swap(0, 2);
+        v[1] += 10;
+        assert_eq!(collect(v), [Some(3), Some(12), Some(0), None]);
+    }
+
+    #[test]
+    fn reverse_append_and_drain() {
+        let mut v: FixedVec<u32, 6> = (0..3).collect();
+        v.reverse();
+        let mut other: FixedVec<u32, 6> = (3..6).collect();
+        v.append(&mut other);
+        assert_eq!(other.len(), 0);
+        assert_eq!(v.len(), 6);
+
+        let drained = collect(v.drain(1..4));
+        assert_eq!(drained, [Some(1), Some(0), Some(3), None, None, None]);
+        v.truncate(2);
+        assert_eq!(collect(v), [Some(2), Some(4), None, None, None, None]);
+    }
+}
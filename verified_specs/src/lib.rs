@@ -0,0 +1,24 @@
+//! Prusti specifications of the core types used by verified drivers, so that every driver doesn't have to copy them.
+//!
+//! * [`option_spec`] and [`result_spec`]: extern specs for `Option` and `Result` and the `peek_*` helpers
+//!   which name their payloads in specifications.
+//! * [`snapshot`]: the [`Snapshot`](snapshot::Snapshot) models which specifications compare values with.
+//! * [`seq_spec`]: ghost operations on Prusti's `Seq`, used to state the contents of containers.
+//! * [`vector_spec`]: `VecWrapper`, a specified wrapper around `Vec`. It is backed by a [`fixed_vec::FixedVec`]
+//!   instead when the `alloc` feature, which is on by default, is turned off.
+//!
+//! The contracts are part of the API and are versioned with the crate: a release which weakens a postcondition
+//! or strengthens a precondition can break the verification of its users, so it bumps the major version
+//! (the minor one before 1.0). Adding specifications or strengthening postconditions doesn't.
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod option_spec;
+pub mod result_spec;
+pub mod snapshot;
+pub mod seq_spec;
+pub mod vector_spec;
+pub mod fixed_vec;
@@ -0,0 +1,90 @@
+//! Extern specifications for `Option`, so that driver code can use its usual methods instead of matching by hand.
+//!
+//! Payloads are compared with `snapshot_equality`, so the specifications also hold for types which are not `Copy`.
+//! Prusti can't reason about the result of a closure, so `map` and `and_then` only specify whether there is a value.
+
+use prusti_contracts::*;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use crate::result_spec::*;
+
+#[extern_spec]
+impl<T> core::option::Option<T> {
+    #[pure]
+    #[ensures(matches!(*self, Some(_)) == result)]
+    pub fn is_some(&self) -> bool;
+
+    #[pure]
+    #[ensures(self.is_some() == !result)]
+    pub fn is_none(&self) -> bool;
+
+    #[requires(self.is_some())]
+    #[ensures(snapshot_equality(&result, peek_option_ref(&self)))]
+    pub fn unwrap(self) -> T;
+
+    #[ensures(self.is_some() ==> snapshot_equality(&result, peek_option_ref(&self)))]
+    #[ensures(self.is_none() ==> snapshot_equality(&result, &default))]
+    pub fn unwrap_or(self, default: T) -> T;
+
+    #[ensures(result.is_some() == self.is_some())]
+    #[ensures(result.is_some() ==> snapshot_equality(peek_option(&result), peek_option_ref(self)))]
+    pub fn as_ref(&self) -> Option<&T>;
+
+    #[ensures(result.is_some() == old(self.is_some()))]
+    #[after_expiry(self.is_some() == old(self.is_some()))]
+    pub fn as_mut(&mut self) -> Option<&mut T>;
+
+    #[ensures(self.is_none())]
+    #[ensures(result.is_some() == old(self.is_some()))]
+    #[ensures(result.is_some() ==> snapshot_equality(peek_option_ref(&result), old(peek_option_ref(self))))]
+    pub fn take(&mut self) -> Option<T>;
+
+    #[ensures(self.is_some())]
+    #[ensures(snapshot_equality(peek_option_ref(self), &value))]
+    #[ensures(result.is_some() == old(self.is_some()))]
+    #[ensures(result.is_some() ==> snapshot_equality(peek_option_ref(&result), old(peek_option_ref(self))))]
+    pub fn replace(&mut self, value: T) -> Option<T>;
+
+    #[ensures(result.is_ok() == self.is_some())]
+    #[ensures(result.is_ok() ==> snapshot_equality(peek_result_ref(&result), peek_option_ref(&self)))]
+    #[ensures(result.is_err() ==> snapshot_equality(peek_err_ref(&result), &err))]
+    pub fn ok_or<E>(self, err: E) -> Result<T, E>;
+
+    #[ensures(result.is_some() == self.is_some())]
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Option<U>;
+
+    #[ensures(self.is_none() ==> result.is_none())]
+    pub fn and_then<U, F: FnOnce(T) -> Option<U>>(self, f: F) -> Option<U>;
+}
+
+#[extern_spec]
+impl<T: Copy + PartialEq> PartialEq for core::option::Option<T> {
+    #[pure]
+    #[ensures(self.is_none() && other.is_none() ==> result == true)]
+    #[ensures(self.is_none() && other.is_some() ==> result == false)]
+    #[ensures(self.is_some() && other.is_none() ==> result == false)]
+    #[ensures(self.is_some() && other.is_some() ==> {
+        let val1 = peek_option(&self);
+        let val2 = peek_option(&other);
+        result == (val1 == val2)
+    })]
+    fn eq(&self, other:&Self) -> bool;
+}
+
+#[pure]
+#[requires(val.is_some())]
+pub fn peek_option<T: Copy>(val: &Option<T>) -> T {
+    match val {
+        Some(val) => *val,
+        None => unreachable!(),
+    }
+}
+
+#[pure]
+#[requires(val.is_some())]
+pub fn peek_option_ref<T>(val: &Option<T>) -> &T {
+    match val {
+        Some(val) => val,
+        None => unreachable!(),
+    }
+}
@@ -0,0 +1,78 @@
+//! The `core_spec` module of `prusti_contracts` specifies `is_ok` and `is_err` for `Result`, and `unwrap` only with
+//! the precondition `self.is_ok()`: specifications can't tell what it returns. A second `extern_spec` of `unwrap`
+//! would clash with that one, so code that needs the payload in its specifications uses `expect`, which is specified below.
+//! The rest of the methods the driver uses are specified here, along with the `peek_*` helpers to name the payloads in specifications.
+//!
+//! `?` converts the error with `From::from` before returning it, so propagating an error is specified by the `From` impl
+//! of the error type.
+
+use prusti_contracts::*;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use crate::option_spec::*;
+
+#[extern_spec]
+impl<T, E> core::result::Result<T, E> {
+    #[ensures(result.is_some() == self.is_ok())]
+    #[ensures(result.is_some() ==> snapshot_equality(peek_option_ref(&result), peek_result_ref(&self)))]
+    pub fn ok(self) -> Option<T>;
+
+    #[ensures(result.is_some() == self.is_err())]
+    #[ensures(result.is_some() ==> snapshot_equality(peek_option_ref(&result), peek_err_ref(&self)))]
+    pub fn err(self) -> Option<E>;
+
+    #[ensures(self.is_ok() ==> snapshot_equality(&result, peek_result_ref(&self)))]
+    #[ensures(self.is_err() ==> snapshot_equality(&result, &default))]
+    pub fn unwrap_or(self, default: T) -> T;
+
+    #[ensures(result.is_ok() == self.is_ok())]
+    #[ensures(result.is_ok() ==> snapshot_equality(peek_result_ref(&result), peek_result_ref(&self)))]
+    pub fn map_err<F, O: FnOnce(E) -> F>(self, op: O) -> Result<T, F>;
+
+    #[ensures(result.is_ok() == self.is_ok())]
+    #[ensures(result.is_err() ==> snapshot_equality(peek_err_ref(&result), peek_err_ref(&self)))]
+    pub fn map<U, F: FnOnce(T) -> U>(self, op: F) -> Result<U, E>;
+}
+
+#[extern_spec]
+impl<T, E: core::fmt::Debug> core::result::Result<T, E> {
+    #[requires(self.is_ok())]
+    #[ensures(snapshot_equality(&result, peek_result_ref(&self)))]
+    pub fn expect(self, msg: &str) -> T;
+}
+
+#[pure]
+#[requires(val.is_ok())]
+pub fn peek_result<T: Copy, E>(val: &Result<T,E>) -> T {
+    match val {
+        Ok(val) => *val,
+        Err(_) => unreachable!(),
+    }
+}
+
+#[pure]
+#[requires(val.is_ok())]
+pub fn peek_result_ref<T, E>(val: &Result<T,E>) -> &T {
+    match val {
+        Ok(val) => val,
+        Err(_) => unreachable!(),
+    }
+}
+
+#[pure]
+#[requires(val.is_err())]
+pub fn peek_err<T, E: Copy>(val: &Result<T,E>) -> E {
+    match val {
+        Ok(_) => unreachable!(),
+        Err(e) => *e,
+    }
+}
+
+#[pure]
+#[requires(val.is_err())]
+pub fn peek_err_ref<T, E>(val: &Result<T,E>) -> &E {
+    match val {
+        Ok(_) => unreachable!(),
+        Err(e) => e,
+    }
+}
\ No newline at end of file
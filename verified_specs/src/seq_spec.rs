@@ -0,0 +1,61 @@
+//! Ghost operations on `Seq`, the mathematical sequences of Prusti, used to state the contents of containers.
+//!
+//! `Seq` itself only provides `empty`, `single`, `concat` and indexing, so pushing, taking a subsequence,
+//! updating an element, reversing and taking a window of a ring are defined here. These functions only exist when verifying, as they are only used in specifications.
+
+#[cfg(prusti)]
+use prusti_contracts::*;
+
+/// `s` with `v` appended.
+#[cfg(prusti)]
+#[pure]
+pub fn seq_push<T: Copy>(s: Seq<T>, v: T) -> Seq<T> {
+    s.concat(Seq::single(v))
+}
+
+/// The elements of `s` in `start..end`.
+#[cfg(prusti)]
+#[pure]
+#[trusted]
+#[requires(Int::new_usize(start) <= Int::new_usize(end) && Int::new_usize(end) <= s.len())]
+#[ensures(result.len() == Int::new_usize(end) - Int::new_usize(start))]
+#[ensures(forall(|i: usize| i < end - start ==> result[i] == s[start + i]))]
+pub fn seq_subsequence<T: Copy>(s: Seq<T>, start: usize, end: usize) -> Seq<T> {
+    unimplemented!()
+}
+
+/// `s` with the element at `index` replaced by `v`.
+#[cfg(prusti)]
+#[pure]
+#[trusted]
+#[requires(Int::new_usize(index) < s.len())]
+#[ensures(result.len() == s.len())]
+#[ensures(result[index] == v)]
+#[ensures(forall(|i: usize| Int::new_usize(i) < s.len() && i != index ==> result[i] == s[i]))]
+pub fn seq_update<T: Copy>(s: Seq<T>, index: usize, v: T) -> Seq<T> {
+    unimplemented!()
+}
+
+/// The `len` elements of `s` in reverse order.
+#[cfg(prusti)]
+#[pure]
+#[trusted]
+#[requires(s.len() == Int::new_usize(len))]
+#[ensures(result.len() == s.len())]
+#[ensures(forall(|i: usize| i < len ==> result[i] == s[len - 1 - i]))]
+pub fn seq_reverse<T: Copy>(s: Seq<T>, len: usize) -> Seq<T> {
+    unimplemented!()
+}
+
+/// The `n` elements of the ring `s` of `len` slots that start at slot `start`, wrapping around to the front at its end.
+#[cfg(prusti)]
+#[pure]
+#[requires(s.len() == Int::new_usize(len))]
+#[requires(start < len && n <= len)]
+pub fn seq_ring_window<T: Copy>(s: Seq<T>, start: usize, n: usize, len: usize) -> Seq<T> {
+    if start + n <= len {
+        seq_subsequence(s, start, start + n)
+    } else {
+        seq_subsequence(s, start, len).concat(seq_subsequence(s, 0, start + n - len))
+    }
+}
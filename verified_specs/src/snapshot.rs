@@ -0,0 +1,45 @@
+//! Models of values which specifications can compare.
+//!
+//! `PartialEq::eq` can't be called from specifications, so the element types of the specified containers implement
+//! [`Snapshot`], a pure function returning a `Copy` model of their value.
+
+use prusti_contracts::*;
+
+/// A `Copy` model of a value, which is what specifications compare.
+pub trait Snapshot {
+    type Model: Copy + PartialEq;
+
+    #[pure]
+    fn snapshot(&self) -> Self::Model;
+}
+
+macro_rules! impl_snapshot_for_primitives {
+    ($($t:ty),*) => {
+        $(
+            impl Snapshot for $t {
+                type Model = $t;
+
+                #[pure]
+                fn snapshot(&self) -> $t {
+                    *self
+                }
+            }
+        )*
+    };
+}
+
+impl_snapshot_for_primitives!(bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    type Model = Option<T::Model>;
+
+    // Pure functions can't call closures, so `Option::map` is not an option here.
+    #[allow(clippy::manual_map)]
+    #[pure]
+    fn snapshot(&self) -> Option<T::Model> {
+        match self {
+            Some(v) => Some(v.snapshot()),
+            None => None,
+        }
+    }
+}
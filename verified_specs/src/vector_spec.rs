@@ -0,0 +1,173 @@
+//! A wrapper around `Vec` with Prusti specifications, since Prusti has no model of `Vec`.
+//!
+//! The contents of a `VecWrapper<T>` are specified through a ghost `view()`, the sequence of the [`Snapshot`]s
+//! of its elements, so that each contract is a single equation on sequences (see [`crate::seq_spec`])
+//! instead of a `forall` over the indices.
+//!
+//! Without the `alloc` feature the elements are stored inline in a [`FixedVec`] of [`FIXED_CAPACITY`] elements instead.
+//! The specifications are the same in both configurations: the methods that add elements require room for them
+//! below [`VEC_CAPACITY`], so they never run out of storage.
+
+use prusti_contracts::*;
+// Only used in specifications, which are erased unless compiling with Prusti
+#[cfg_attr(not(prusti), allow(unused_imports))]
+use crate::{option_spec::*, seq_spec::*};
+use crate::snapshot::Snapshot;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(not(feature = "alloc"))]
+use crate::fixed_vec::FixedVec;
+
+/// The number of elements a `VecWrapper` can hold without an allocator.
+#[cfg(not(feature = "alloc"))]
+pub const FIXED_CAPACITY: usize = 2048;
+
+/// The most elements a `VecWrapper` holds: the capacity of the inline storage without an allocator,
+/// and the most elements a `Vec` can have with one.
+#[cfg(not(feature = "alloc"))]
+pub const VEC_CAPACITY: usize = FIXED_CAPACITY;
+#[cfg(feature = "alloc")]
+pub const VEC_CAPACITY: usize = isize::MAX as usize;
+
+#[cfg(feature = "alloc")]
+type Storage<T> = Vec<T>;
+#[cfg(not(feature = "alloc"))]
+type Storage<T> = FixedVec<T, FIXED_CAPACITY>;
+
+pub struct VecWrapper<T>{
+    pub(crate) v: Storage<T>
+}
+
+impl<T> VecWrapper<T> {
+
+    #[trusted]
+    #[ensures(result.len() == 0)]
+    pub fn new() -> Self {
+        VecWrapper{ v: Storage::new() }
+    }
+
+    #[trusted]
+    #[pure]
+    #[ensures(result <= VEC_CAPACITY)]
+    pub fn len(&self) -> usize {
+        self.v.len()
+    }
+
+    #[trusted]
+    #[pure]
+    #[requires(0 <= index && index < self.len())]
+    pub fn index(&self, index: usize) -> &T {
+        &self.v[index]
+    }
+}
+
+impl<T: Snapshot> VecWrapper<T> {
+    /// The snapshots of the elements, in order. Only exists for specifications.
+    #[cfg(prusti)]
+    #[pure]
+    #[trusted]
+    #[ensures(result.len() == Int::new_usize(self.len()))]
+    #[ensures(forall(|i: usize| i < self.len() ==> result[i] == self.index(i).snapshot()))]
+    pub fn view(&self) -> Seq<T::Model> {
+        unimplemented!()
+    }
+
+    #[trusted]
+    #[requires(0 <= index && index < self.len())]
+    #[ensures(result.snapshot() == old(self.index(index).snapshot()))]
+    #[after_expiry(self.len() == old(self.len()))]
+    #[after_expiry(self.view() == seq_update(old(self.view()), index, before_expiry(result).snapshot()))]
+    pub fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.v[index]
+    }
+
+    #[trusted]
+    #[requires(self.len() < VEC_CAPACITY)]
+    #[ensures(self.len() == old(self.len()) + 1)]
+    #[ensures(self.view() == seq_push(old(self.view()), old(value.snapshot())))]
+    pub fn push(&mut self, value: T) {
+        self.v.push(value);
+    }
+
+    #[trusted]
+    #[ensures(result.is_none() == (old(self.len()) == 0))]
+    #[ensures(result.is_none() ==> self.len() == old(self.len()) && self.view() == old(self.view()))]
+    #[ensures(result.is_some() ==> self.len() == old(self.len()) - 1)]
+    #[ensures(result.is_some() ==> old(self.view()) == seq_push(self.view(), peek_option_ref(&result).snapshot()))]
+    pub fn pop(&mut self) -> Option<T> {
+        self.v.pop()
+    }
+
+    /// Inserts `element` at `index`, shifting all elements after it to the right.
+    #[trusted]
+    #[requires(index <= self.len())]
+    #[requires(self.len() < VEC_CAPACITY)]
+    #[ensures(self.len() == old(self.len()) + 1)]
+    #[ensures(self.view() == seq_subsequence(old(self.view()), 0, index)
+        .concat(Seq::single(old(element.snapshot())))
+        .concat(seq_subsequence(old(self.view()), index, old(self.len()))))]
+    pub fn insert(&mut self, index: usize, element: T) {
+        self.v.insert(index, element);
+    }
+
+    /// Removes and returns the element at `index`, shifting all elements after it to the left.
+    #[trusted]
+    #[requires(index < self.len())]
+    #[ensures(self.len() == old(self.len()) - 1)]
+    #[ensures(result.snapshot() == old(self.index(index).snapshot()))]
+    #[ensures(self.view() == seq_subsequence(old(self.view()), 0, index)
+        .concat(seq_subsequence(old(self.view()), index + 1, old(self.len()))))]
+    pub fn remove(&mut self, index: usize) -> T {
+        self.v.remove(index)
+    }
+
+    /// Swaps the elements at `a` and `b`.
+    #[trusted]
+    #[requires(a < self.len() && b < self.len())]
+    #[ensures(self.len() == old(self.len()))]
+    #[ensures(self.view() == seq_update(
+        seq_update(old(self.view()), a, old(self.index(b).snapshot())),
+        b, old(self.index(a).snapshot())
+    ))]
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.v.swap(a, b);
+    }
+
+    /// Reverses the order of the elements.
+    #[trusted]
+    #[ensures(self.len() == old(self.len()))]
+    #[ensures(self.view() == seq_reverse(old(self.view()), old(self.len())))]
+    pub fn reverse(&mut self) {
+        self.v.reverse();
+    }
+
+    /// Drops all elements from `len` on. Does nothing if the vector isn't longer than `len`.
+    #[trusted]
+    #[ensures(old(self.len()) <= len ==> self.len() == old(self.len()) && self.view() == old(self.view()))]
+    #[ensures(old(self.len()) > len ==> self.len() == len && self.view() == seq_subsequence(old(self.view()), 0, len))]
+    pub fn truncate(&mut self, len: usize) {
+        self.v.truncate(len);
+    }
+
+    /// Moves all elements of `other` to the end of this vector, keeping their order.
+    #[trusted]
+    #[requires(self.len() + other.len() <= VEC_CAPACITY)]
+    #[ensures(self.len() == old(self.len()) + old(other.len()))]
+    #[ensures(other.len() == 0)]
+    #[ensures(self.view() == old(self.view()).concat(old(other.view())))]
+    pub fn extend(&mut self, other: &mut VecWrapper<T>) {
+        self.v.append(&mut other.v);
+    }
+
+    /// Removes the elements in `start..end` and returns them, keeping their order.
+    #[trusted]
+    #[requires(start <= end && end <= self.len())]
+    #[ensures(result.len() == end - start)]
+    #[ensures(self.len() == old(self.len()) - (end - start))]
+    #[ensures(result.view() == seq_subsequence(old(self.view()), start, end))]
+    #[ensures(self.view() == seq_subsequence(old(self.view()), 0, start)
+        .concat(seq_subsequence(old(self.view()), end, old(self.len()))))]
+    pub fn drain(&mut self, start: usize, end: usize) -> VecWrapper<T> {
+        VecWrapper { v: self.v.drain(start..end).collect() }
+    }
+}